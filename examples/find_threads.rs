@@ -27,7 +27,7 @@ fn main() {
     // This is the absolute minimum required by the API. This is done by
     // sleeping the thread until the time is up, so don't call it at intervals
     // any less than 10 seconds.
-    for mut candidate in &mut sticky_candidates {
+    for candidate in &mut sticky_candidates {
         candidate.update().expect("Failed to update");
     }
 }
@@ -0,0 +1,183 @@
+//! Future returning variants of the fetch methods of `Board` and `Thread`,
+//! behind the `async` feature.
+//!
+//! They are built on the async client of `reqwest`, so no thread is blocked
+//! or spawned per request: the rate limit is waited for with a timer, and the
+//! client is only locked to copy out what a request needs. The futures must
+//! run on a tokio runtime. They share the client, and with it the rate limit,
+//! and the caches of the board with the blocking methods.
+
+use futures::{future, Future};
+use futures::future::Either;
+use reqwest::header::HeaderMap;
+
+use board::Cached;
+
+/// A boxed future of one of the async fetch methods.
+pub type AsyncFuture<T> = Box<dyn Future<Item=T, Error=::Error> + Send>;
+
+impl ::Board {
+    /// Get the catalog like `Board::catalog`, without blocking.
+    pub fn catalog_async(&self) -> AsyncFuture<Option<::Catalog>> {
+        let (since, etag) = self.catalog_validators();
+        let res = self.sender().get_conditional_async(
+            &self.catalog_url(), since, etag.as_deref());
+        let board = self.clone();
+        Box::new(res.and_then(move |res| {
+            board.catalog_received(res.status, &res.headers, res.text)
+                .map(|catalog| catalog.map(|(catalog, _)| catalog))
+        }))
+    }
+
+    /// Get a thread like `Board::get_thread`, without blocking. A cached
+    /// thread that doesn't need an update resolves right away.
+    pub fn get_thread_async(&self, thread_no: u64) -> AsyncFuture<::Thread> {
+        match self.cached_thread(thread_no) {
+            Some(Cached::Fresh(thread)) => Box::new(future::ok(thread)),
+            Some(Cached::Expired) => {
+                self.thread_cache.write().unwrap().remove(thread_no);
+                Box::new(future::err(::Error::ThreadNotFound(thread_no)))
+            },
+            Some(Cached::Stale(request)) => {
+                // On error the cached copy is left as it was.
+                let board = self.clone();
+                Box::new(request.send_async().and_then(move |response| {
+                    match board.write_back(thread_no, response) {
+                        Some(res) => Either::A(future::result(res)),
+                        // The thread was removed meanwhile, so it is
+                        // fetched anew.
+                        None => Either::B(board.fetch_thread_async(thread_no))
+                    }
+                }))
+            },
+            None => self.fetch_thread_async(thread_no)
+        }
+    }
+
+    /// Requests a thread that isn't cached, and caches it.
+    fn fetch_thread_async(&self, thread_no: u64) -> AsyncFuture<::Thread> {
+        let res = self.sender().get_async(&self.thread_url(thread_no),
+                                          HeaderMap::new());
+        let board = self.clone();
+        Box::new(res.and_then(move |res| {
+            board.thread_received(thread_no, res.status, &res.text)
+        }))
+    }
+}
+
+impl ::Thread {
+    /// Updates the thread like `Thread::update`, without blocking. Takes the
+    /// thread by value and gives it back along with whether it changed.
+    pub fn update_async(mut self) -> AsyncFuture<(::Thread, bool)> {
+        match self.update_request() {
+            Some(request) => Box::new(request.send_async().map(
+                move |response| {
+                    let changed = !self.apply(response).is_empty();
+                    (self, changed)
+                })),
+            None => Box::new(future::ok((self, false)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use futures::Future;
+    use tokio::runtime::current_thread::Runtime;
+
+    fn board(base: &str) -> ::Board {
+        let client = ::Client::new().unwrap();
+        let board = ::Board::new_unchecked(Arc::new(Mutex::new(client)), "g");
+        board.client.lock().unwrap().set_api_base(base);
+        board
+    }
+
+    fn topic(no: u64) -> ::Post {
+        ::serde_json::from_str(&format!(
+            r#"{{"no": {}, "resto": 0, "now": "", "time": 0}}"#, no)).unwrap()
+    }
+
+    #[test]
+    fn catalog_async() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("200 OK", r#"[{"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0}]}]"#),
+            ::mock::response("304 Not Modified", "")]);
+        let g = board(&base);
+        g.client.lock().unwrap().set_min_interval(Duration::from_millis(0));
+
+        let mut runtime = Runtime::new().unwrap();
+        let catalog = runtime.block_on(g.catalog_async()).unwrap().unwrap();
+        assert_eq!(1, catalog.iter_topics().count());
+        assert!(g.thread_cache.read().unwrap().contains(1));
+        assert!(runtime.block_on(g.catalog_async()).unwrap().is_none());
+
+        let requests = server.join().unwrap();
+        assert!(requests[1].to_lowercase().contains("if-modified-since"));
+    }
+
+    #[test]
+    fn get_thread_async() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("200 OK", r#"{"posts": [{"no": 1, "resto": 0,
+                "now": "", "time": 0}, {"no": 2, "resto": 1, "now": "",
+                "time": 0}]}"#),
+            ::mock::response("404 Not Found", "")]);
+        let g = board(&base);
+        g.client.lock().unwrap().set_min_interval(Duration::from_millis(0));
+        // A stub from the catalog is stale, so it is updated.
+        g.thread_cache.write().unwrap().insert(::Thread::from_topic(
+            topic(1), "g", g.client.clone()));
+
+        let mut runtime = Runtime::new().unwrap();
+        let thread = runtime.block_on(g.get_thread_async(1)).unwrap();
+        assert_eq!(2, thread.posts().len());
+        assert_eq!(2, g.thread_cache.read().unwrap()
+                   .get(1).unwrap().posts().len());
+
+        // A miss that 404s.
+        match runtime.block_on(g.get_thread_async(3)) {
+            Err(::Error::ThreadNotFound(3)) => (),
+            res => panic!("Expected ThreadNotFound, got {:?}", res)
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn update_async_expired() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("404 Not Found", "")]);
+        let g = board(&base);
+        let thread = ::Thread::from_topic(topic(1), "g", g.client.clone());
+
+        let mut runtime = Runtime::new().unwrap();
+        let (thread, changed) = runtime.block_on(thread.update_async())
+            .unwrap();
+        assert!(!changed);
+        assert!(thread.expired);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn async_requests_share_the_rate_limit() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("404 Not Found", ""),
+            ::mock::response("404 Not Found", "")]);
+        let g = board(&base);
+        g.client.lock().unwrap().set_min_interval(Duration::from_millis(500));
+
+        // Both requests run on a single thread, so the second one waits for
+        // its slot without blocking the first.
+        let mut runtime = Runtime::new().unwrap();
+        let start = Instant::now();
+        let both = g.get_thread_async(1).then(Ok::<_, ()>)
+            .join(g.get_thread_async(2).then(Ok::<_, ()>));
+        let (first, second) = runtime.block_on(both).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert!(first.is_err() && second.is_err());
+        assert_eq!(2, server.join().unwrap().len());
+    }
+}
@@ -1,155 +1,862 @@
+use std::cmp::{self, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::io::Read;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration as StdDuration;
 
-use regex::RegexBuilder;
-use chrono::{DateTime, UTC};
-use reqwest::StatusCode;
+use regex::{escape, Regex, RegexBuilder};
+use chrono::{DateTime, NaiveDateTime, UTC};
+use reqwest::{Response, StatusCode};
+use reqwest::header::HeaderMap;
 
 /// A `Board` represents a 4chan board. Automatically caches threads when
 /// `catalog` is run. Using `find_cached` or `get_thread` will lazily update
 /// the requested thread(s).
-#[derive(Debug)]
+///
+/// Cloning a `Board` is cheap, and the clone shares the client and caches of
+/// the original.
+#[derive(Clone, Debug)]
 pub struct Board {
     pub name: String,
     pub client: Arc<Mutex<::Client>>,
-    pub thread_cache: Arc<Mutex<::ThreadCache>>,
-    catalog_last_modified: Arc<Mutex<Option<DateTime<UTC>>>>
+    pub thread_cache: Arc<RwLock<::ThreadCache>>,
+    catalog_last_modified: Arc<Mutex<Option<DateTime<UTC>>>>,
+    catalog_etag: Arc<Mutex<Option<String>>>,
+    // The last catalog received.
+    catalog_cache: Arc<Mutex<Option<Catalog>>>,
+    threads_last_modified: Arc<Mutex<Option<DateTime<UTC>>>>,
+    archive_last_modified: Arc<Mutex<Option<DateTime<UTC>>>>,
+    threads_cache: Arc<Mutex<Vec<ThreadSummary>>>,
+    archive_cache: Arc<Mutex<Vec<u64>>>
 }
 
+#[allow(deprecated)]
 impl Board {
-    /// Creates a new `Board`.
+    /// Creates a new `Board`. Returns `Error::InvalidBoardName` if the board
+    /// is not in the list of boards, which is fetched if it hasn't been yet.
     pub fn new(client: Arc<Mutex<::Client>>, name: &str) -> ::Result<Board> {
-        if !client.lock().unwrap().is_valid_board(name) {
+        try!(::client::ensure_boards(&client));
+        if !try!(client.lock().unwrap().is_valid_board(name)) {
             return Err(::Error::InvalidBoardName)
         }
 
-        Ok(Board {
-            client: client,
+        Ok(Board::new_unchecked(client, name))
+    }
+
+    /// Creates a new `Board` without checking that it exists, so no request
+    /// is made. Use it for names that were already validated or with a test
+    /// server. Requests to a board that doesn't exist fail with 404s.
+    pub fn new_unchecked(client: Arc<Mutex<::Client>>, name: &str) -> Board {
+        Board {
+            client,
             name: name.to_string(),
-            thread_cache: Arc::new(Mutex::new(::ThreadCache::new())),
-            catalog_last_modified: Arc::new(Mutex::new(None))
-        })
+            thread_cache: Arc::new(RwLock::new(::ThreadCache::new())),
+            catalog_last_modified: Arc::new(Mutex::new(None)),
+            catalog_etag: Arc::new(Mutex::new(None)),
+            catalog_cache: Arc::new(Mutex::new(None)),
+            threads_last_modified: Arc::new(Mutex::new(None)),
+            archive_last_modified: Arc::new(Mutex::new(None)),
+            threads_cache: Arc::new(Mutex::new(Vec::new())),
+            archive_cache: Arc::new(Mutex::new(Vec::new()))
+        }
+    }
+
+    /// Creates a `Board` for each name, like `new`. Every board shares the
+    /// client, and so its rate limit, but has its own cache. Returns the
+    /// boards that were created, in order, and the names that failed with
+    /// their errors.
+    pub fn new_batch(client: Arc<Mutex<::Client>>, names: &[&str])
+        -> (Vec<Board>, Vec<(String, ::Error)>) {
+        let mut boards = Vec::new();
+        let mut errors = Vec::new();
+        for name in names {
+            match Board::new(client.clone(), name) {
+                Ok(board) => boards.push(board),
+                Err(e) => errors.push((name.to_string(), e))
+            }
+        }
+        (boards, errors)
     }
 
     /// Get a board's current `Catalog`. Automatically updates the current
     /// thread cache. Returns `Some<Catalog>` if the catalog was updated,
     /// and `None` if the catalog was not modified since the last request.
     pub fn catalog(&self) -> ::Result<Option<Catalog>> {
-        let mut res = match *self.catalog_last_modified.lock().unwrap() {
-            None => {
-                try!(self.client.lock().unwrap().get(
-                        &format!("https://a.4cdn.org/{}/catalog.json",
-                                 self.name),
-                        None))
-            },
-            Some(dt) => {
-                // If-Modified-Since: Sat, 29 Oct 1994 19:43:31 GMT
-                //                    %a,  %d %b  %Y   %T       GMT
-                let format = "%a, %d %b %Y %T GMT";
-                let fmt_date = dt.format(&format).to_string();
-                try!(self.client.lock().unwrap().get(
-                        &format!("https://a.4cdn.org/{}/catalog.json",
-                                 self.name),
-                        Some(::IfModifiedSince(fmt_date))))
+        Ok(try!(self.fetch_catalog(false)).map(|(catalog, _)| catalog))
+    }
+
+    /// Get a board's current `Catalog` even if it was not modified since the
+    /// last request, by not sending If-Modified-Since. Otherwise the same as
+    /// `catalog`.
+    pub fn catalog_force(&self) -> ::Result<Catalog> {
+        match try!(self.fetch_catalog(true)) {
+            Some((catalog, _)) => Ok(catalog),
+            // Can't happen without If-Modified-Since.
+            None => Err(::Error::UnexpectedStatus(StatusCode::NOT_MODIFIED))
+        }
+    }
+
+    /// Get the catalog exactly as the server sent it, a json array of pages.
+    /// Useful to store the response or to read fields `Catalog` doesn't
+    /// model.
+    ///
+    /// Shares its If-Modified-Since state with `catalog`, and updates the
+    /// thread cache the same way. Returns `None` if the catalog was not
+    /// modified since the last request by either method.
+    pub fn catalog_raw(&self) -> ::Result<Option<String>> {
+        Ok(try!(self.fetch_catalog(false)).map(|(_, raw)| raw))
+    }
+
+    /// Get the catalog like `catalog` and compare it with the previous one,
+    /// to learn which threads were created, bumped or are gone since. On
+    /// the first call every thread is new. Returns `None` if the catalog was
+    /// not modified since the last request.
+    pub fn catalog_diff(&self) -> ::Result<Option<CatalogDiff>> {
+        let previous = self.catalog_cache.lock().unwrap().clone()
+            .unwrap_or_else(|| Catalog { pages: Vec::new() });
+        Ok(try!(self.catalog()).map(|catalog| previous.diff(&catalog)))
+    }
+
+    /// Watches the catalog, polling it every interval and calling `f` with
+    /// the topics that match the query and didn't match on a previous poll,
+    /// so that a thread is only notified once. Topics matching on the first
+    /// poll are notified too. Stops when `f` returns `WatchControl::Stop`.
+    ///
+    /// The query is a regex matched like in `find_cached`. Polls send
+    /// If-Modified-Since like `catalog`, so that polling an idle board is
+    /// cheap, and go through the client, so they are still rate limited.
+    pub fn watch_catalog<F>(&self, query: &str, interval: StdDuration, f: F)
+        -> ::Result<()>
+        where F: FnMut(Vec<::Post>) -> ::WatchControl
+    {
+        self.watch_catalog_with_handle(query, interval, &::WatchHandle::new(),
+                                       f)
+    }
+
+    /// Watches the catalog like `watch_catalog`, but also stops once the
+    /// handle is stopped, which can be done from another thread. The handle
+    /// is checked before every poll and while sleeping between them.
+    pub fn watch_catalog_with_handle<F>(&self,
+                                        query: &str,
+                                        interval: StdDuration,
+                                        handle: &::WatchHandle,
+                                        mut f: F) -> ::Result<()>
+        where F: FnMut(Vec<::Post>) -> ::WatchControl
+    {
+        let regex = try!(build_regex(query));
+        let mut seen = HashSet::new();
+        let mut first = true;
+        loop {
+            if handle.is_stopped() {
+                return Ok(())
+            }
+            // The catalog may be unmodified since an earlier call to
+            // `catalog`, in which case the first poll uses the cached one.
+            let catalog = match try!(self.catalog()) {
+                None if first => self.catalog_cache.lock().unwrap().clone(),
+                catalog => catalog
+            };
+            first = false;
+
+            if let Some(catalog) = catalog {
+                let topics: Vec<::Post> = catalog.iter_topics()
+                    .filter(|t| t.is_match(&regex) && seen.insert(t.no))
+                    .cloned()
+                    .collect();
+                if !topics.is_empty() {
+                    if let ::WatchControl::Stop = f(topics) {
+                        return Ok(())
+                    }
+                }
             }
+            if !::sleep_unless_stopped(interval, handle) {
+                return Ok(())
+            }
+        }
+    }
+
+    /// Get a page of the catalog, numbered from 0 like `Catalog::page`.
+    /// Uses the last catalog received by `catalog` or `catalog_force`, and
+    /// only fetches the catalog if there is none yet. Returns `None` if the
+    /// page doesn't exist.
+    pub fn catalog_page(&self, page: u8) -> ::Result<Option<Page>> {
+        let cached = self.catalog_cache.lock().unwrap().as_ref()
+            .map(|catalog| catalog.page(page).cloned());
+        if let Some(page) = cached {
+            return Ok(page)
+        }
+        Ok(try!(self.catalog_force()).page(page).cloned())
+    }
+
+    /// Get the number of pages of the board from boards.json, which is
+    /// fetched if the client doesn't have it yet. `None` if the board isn't
+    /// listed.
+    pub fn max_pages(&self) -> ::Result<Option<u32>> {
+        try!(::client::ensure_boards(&self.client));
+        let mut client = self.client.lock().unwrap();
+        Ok(try!(client.board_info(&self.name)).map(|info| info.pages()))
+    }
+
+    /// Get when the catalog was last modified according to the server, as of
+    /// the last successful `catalog` request. `None` if there was none.
+    pub fn catalog_last_modified(&self) -> Option<DateTime<UTC>> {
+        *self.catalog_last_modified.lock().unwrap()
+    }
+
+    /// Requests the catalog, parses it and caches its topics. Returns both
+    /// the parsed catalog and the raw response body. If-Modified-Since is
+    /// only sent if `force` is `false`.
+    fn fetch_catalog(&self, force: bool)
+        -> ::Result<Option<(Catalog, String)>> {
+        let url = self.catalog_url();
+        let mut res = if force {
+            try!(self.sender().get(&url, None))
+        } else {
+            let (since, etag) = self.catalog_validators();
+            try!(self.sender().get_conditional(&url, since, etag.as_deref()))
         };
+        let buf = try!(::read_text(&mut res));
+        self.catalog_received(res.status(), res.headers(), buf)
+    }
 
-        match *res.status() {
-            StatusCode::Ok => {
-                *self.catalog_last_modified.lock().unwrap() = Some(UTC::now());
-                let mut buf = String::new();
-                try!(res.read_to_string(&mut buf));
-                let corrected = r#"{"pages":"#.to_string() + &buf + "}";
-                let catalog: Catalog = try!(::serde_json::from_str(&corrected));
+    /// Get the url of the catalog.
+    pub(crate) fn catalog_url(&self) -> String {
+        self.api_url(&format!("/{}/catalog.json", self.name))
+    }
+
+    /// Get the validators of the last catalog. They are copied out so that
+    /// the locks are released before the request.
+    pub(crate) fn catalog_validators(&self)
+        -> (Option<::IfModifiedSince>, Option<String>) {
+        let since = self.catalog_last_modified.lock().unwrap()
+            .map(|dt| ::IfModifiedSince::from_date(&dt));
+        (since, self.catalog_etag.lock().unwrap().clone())
+    }
+
+    /// Handles a response to a catalog request, see `fetch_catalog`.
+    pub(crate) fn catalog_received(&self,
+                                   status: StatusCode,
+                                   headers: &HeaderMap,
+                                   buf: String)
+        -> ::Result<Option<(Catalog, String)>> {
+        match status {
+            StatusCode::OK => {
+                *self.catalog_last_modified.lock().unwrap() =
+                    Some(::last_modified(headers));
+                *self.catalog_etag.lock().unwrap() = ::etag(headers);
+                let catalog = try!(Catalog::from_pages_json(&buf));
 
                 for topic in catalog.topics() {
-                    self.thread_cache.lock().unwrap()
+                    self.thread_cache.write().unwrap()
                         .insert(::Thread::from_topic(topic.clone(),
                         &self.name, self.client.clone()));
                 }
 
-                Ok(Some(catalog))
+                *self.catalog_cache.lock().unwrap() = Some(catalog.clone());
+                info!("Updated catalog of /{}/: {} topics", self.name,
+                      catalog.iter_topics().count());
+                Ok(Some((catalog, buf)))
+            },
+            StatusCode::NOT_MODIFIED => {
+                info!("Catalog of /{}/ not modified", self.name);
+                Ok(None)
+            },
+            status => Err(::Error::UnexpectedStatus(status))
+        }
+    }
+
+    /// Get a summary of every live thread on the board from the threads.json
+    /// endpoint. This is much lighter than the catalog, as it only contains
+    /// thread numbers, reply counts and modification times. Returns `None` if
+    /// the threads list was not modified since the last request.
+    pub fn threads(&self) -> ::Result<Option<Vec<ThreadSummary>>> {
+        let mut res = try!(self.get_if_modified(
+                &self.api_url(&format!("/{}/threads.json", self.name)),
+                &self.threads_last_modified));
+
+        match res.status() {
+            StatusCode::OK => {
+                *self.threads_last_modified.lock().unwrap() =
+                    Some(::last_modified(res.headers()));
+                let mut buf = String::new();
+                try!(res.read_to_string(&mut buf));
+                let pages: Vec<ThreadsPage> = try!(
+                    ::serde_json::from_str(&buf));
+
+                let mut summaries = Vec::new();
+                for page in pages {
+                    for thread in page.threads {
+                        summaries.push(ThreadSummary {
+                            no: thread.no,
+                            last_modified: DateTime::<UTC>::from_utc(
+                                NaiveDateTime::from_timestamp(
+                                    thread.last_modified, 0), UTC),
+                            replies: thread.replies,
+                            page: page.page
+                        });
+                    }
+                }
+
+                *self.threads_cache.lock().unwrap() = summaries.clone();
+
+                Ok(Some(summaries))
             },
-            StatusCode::NotModified => {
+            StatusCode::NOT_MODIFIED => {
                 Ok(None)
             },
-            _ => Err(::Error::UnexpectedResponse)
+            status => Err(::Error::UnexpectedStatus(status))
+        }
+    }
+
+    /// Get the thread numbers of every archived thread on the board. Pass
+    /// them to `get_thread` to get the archived content.
+    ///
+    /// The list is cached, so if it was not modified since the last request
+    /// the cached list is returned. Returns `Error::ArchiveNotAvailable` if
+    /// the board does not have an archive.
+    pub fn archived(&self) -> ::Result<Vec<u64>> {
+        let mut res = try!(self.get_if_modified(
+                &self.api_url(&format!("/{}/archive.json", self.name)),
+                &self.archive_last_modified));
+
+        match res.status() {
+            StatusCode::OK => {
+                *self.archive_last_modified.lock().unwrap() =
+                    Some(::last_modified(res.headers()));
+                let mut buf = String::new();
+                try!(res.read_to_string(&mut buf));
+                let archived: Vec<u64> = try!(::serde_json::from_str(&buf));
+                *self.archive_cache.lock().unwrap() = archived.clone();
+
+                Ok(archived)
+            },
+            StatusCode::NOT_MODIFIED => {
+                Ok(self.archive_cache.lock().unwrap().clone())
+            },
+            StatusCode::NOT_FOUND => Err(::Error::ArchiveNotAvailable),
+            status => Err(::Error::UnexpectedStatus(status))
         }
     }
 
+    /// Get the url of a JSON API path from the client.
+    fn api_url(&self, path: &str) -> String {
+        self.client.lock().unwrap().api_url(path)
+    }
+
+    /// Get a `Sender` from the client. The client is only locked for this,
+    /// so that waiting for the rate limit doesn't block other boards.
+    pub(crate) fn sender(&self) -> ::client::Sender {
+        self.client.lock().unwrap().sender()
+    }
+
+    /// Makes a GET request to the url, adding an If-Modified-Since header if
+    /// the endpoint has been successfully requested before.
+    fn get_if_modified(&self,
+                       url: &str,
+                       last_modified: &Mutex<Option<DateTime<UTC>>>)
+        -> ::Result<Response> {
+        // Copy the date out so that the lock is released before the request.
+        let last_modified = *last_modified.lock().unwrap();
+        let header = last_modified.map(|dt| ::IfModifiedSince::from_date(&dt));
+        self.sender().get_if_modified_since(url, header)
+    }
+
+    /// Compiles a query the way the searches do, case insensitive and with
+    /// unicode. Pass the regex to `Post::is_match` or `Thread::is_match` to
+    /// run the same query many times without compiling it again.
+    pub fn compile_query(query: &str) -> ::Result<Regex> {
+        build_regex(query)
+    }
+
     /// Finds any threads in the cache that contain the query string in one of
     /// the OP's name, comment, subject, or filename. The search is case
     /// insensitive and uses unicode.
     ///
-    /// The threads are updated before they are returned. Automatically
-    /// excludes expired threads.
+    /// The query is a regex. Use `find_cached_literal` to search for a plain
+    /// string.
+    ///
+    /// The threads are updated before they are returned, unless they are
+    /// younger than the TTL of the cache. Automatically excludes expired
+    /// threads.
     pub fn find_cached(&self, query: &str) -> ::Result<Vec<::Thread>> {
-        let mut regex_builder = RegexBuilder::new(query);
-        let regex = try!(regex_builder
-                         .case_insensitive(true)
-                         .unicode(true)
-                         .build());
-
-        let mut threads = self.thread_cache.lock().unwrap().threads
-            .values()
-            .filter(|&t| t.is_match(&regex))
-            .cloned()
-            .collect::<Vec<::Thread>>();
+        self.find_cached_in(query, ::SearchFields::all())
+    }
+
+    /// Finds threads in the cache like `find_cached`, but the query is
+    /// matched literally rather than as a regex.
+    pub fn find_cached_literal(&self, query: &str) -> ::Result<Vec<::Thread>> {
+        self.find_cached(&escape(query))
+    }
 
-        // TODO: A returned thread is cloned twice. Needs refactoring.
+    /// Finds threads in the cache like `find_cached`, but only matches the
+    /// query against the given fields of the OP.
+    pub fn find_cached_in(&self, query: &str, fields: ::SearchFields)
+        -> ::Result<Vec<::Thread>> {
+        let regex = try!(build_regex(query));
+
+        // Threads that don't need an update are cloned under the read lock.
+        // For the others only what the update needs is taken, so that the
+        // cache isn't locked during the requests. They stay cached
+        // meanwhile, so concurrent lookups still find them.
         let mut return_threads = Vec::new();
-        for mut thread in &mut threads {
-            try!(thread.update());
-            if !thread.expired {
-                return_threads.push(thread.clone());
-            } else {
-                // Update cache, removing expired threads
-                self.thread_cache.lock().unwrap().remove(thread.topic.no);
+        let mut expired = Vec::new();
+        let mut requests = Vec::new();
+        {
+            let cache = self.thread_cache.read().unwrap();
+            for thread in cache.threads.values()
+                .filter(|&t| t.is_match_in(&regex, fields)) {
+                match lookup(&cache, thread) {
+                    Cached::Fresh(thread) => return_threads.push(thread),
+                    Cached::Stale(request) =>
+                        requests.push((thread.op().no, request)),
+                    Cached::Expired => expired.push(thread.op().no)
+                }
+            }
+        }
+
+        // Update the threads concurrently.
+        let responses = ::pool::map(requests, |(thread_no, request)| {
+            (thread_no, request.send())
+        });
+
+        let mut error = None;
+        let mut cache = self.thread_cache.write().unwrap();
+        for thread_no in expired {
+            cache.remove(thread_no);
+        }
+        for (thread_no, res) in responses {
+            match res {
+                // The cached copy is left as it was.
+                Err(e) => if error.is_none() {
+                    error = Some(e);
+                },
+                // The response is applied to the cached copy, which is then
+                // cloned once. Threads that expired, or were removed
+                // meanwhile, are left out.
+                Ok(response) => if cache.apply(thread_no, response).is_some() {
+                    if let Some(thread) = cache.get(thread_no) {
+                        return_threads.push(thread.clone());
+                    }
+                }
             }
         }
 
-        Ok(threads)
+        if let Some(e) = error {
+            return Err(e)
+        }
+
+        Ok(return_threads)
+    }
+
+    /// Get many threads by number, like `get_thread`. The threads are
+    /// fetched concurrently, and a failure to get one of them doesn't stop
+    /// the others. The results are in the order of the numbers.
+    pub fn get_threads(&self, thread_nos: &[u64]) -> Vec<::Result<::Thread>> {
+        let board = self.clone();
+        ::pool::map(thread_nos.to_vec(), move |no| board.get_thread(no))
+    }
+
+    /// Updates every thread in the cache and removes the ones that expired.
+    /// Unchanged threads only cost a 304 response. The threads are updated
+    /// concurrently, and a failure to update one of them doesn't stop the
+    /// others; the thread is kept in the cache and the error is reported.
+    pub fn refresh_all(&self) -> ::Result<RefreshSummary> {
+        // Only what the updates need is taken, so that the cache isn't
+        // locked during the requests. The threads stay cached meanwhile.
+        let mut expired = Vec::new();
+        let mut requests = Vec::new();
+        for thread in self.thread_cache.read().unwrap().threads.values() {
+            match thread.update_request() {
+                Some(request) => requests.push((thread.op().no, request)),
+                None => expired.push(thread.op().no)
+            }
+        }
+
+        let responses = ::pool::map(requests, |(thread_no, request)| {
+            (thread_no, request.send())
+        });
+
+        let mut summary = RefreshSummary::default();
+        let mut cache = self.thread_cache.write().unwrap();
+        for thread_no in expired {
+            cache.remove(thread_no);
+            summary.expired += 1;
+        }
+        for (thread_no, res) in responses {
+            match res {
+                // The cached copy is left as it was.
+                Err(e) => summary.failed.push((thread_no, e)),
+                Ok(response) => match cache.apply(thread_no, response) {
+                    // Removed meanwhile.
+                    None => {},
+                    Some(_) if !cache.contains(thread_no) =>
+                        summary.expired += 1,
+                    Some(ref diff) if diff.is_empty() =>
+                        summary.unchanged += 1,
+                    Some(_) => summary.updated += 1
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Get a clone of every thread in the cache, in no particular order. No
+    /// request is made, so the threads are as they were last fetched.
+    pub fn cached_threads(&self) -> Vec<::Thread> {
+        self.thread_cache.read().unwrap().threads.values().cloned().collect()
+    }
+
+    /// Removes the expired threads from the cache. No request is made, so
+    /// only threads already known to be expired are removed. Returns the
+    /// number of threads removed.
+    pub fn purge_expired(&self) -> usize {
+        self.thread_cache.write().unwrap().purge_expired()
+    }
+
+    /// Removes the threads that are no longer live from the cache, using the
+    /// threads.json endpoint rather than updating every cached thread. If
+    /// the threads list was not modified since the last request, the list
+    /// from that request is used. Returns the numbers of the removed threads.
+    pub fn prune_dead(&self) -> ::Result<Vec<u64>> {
+        let live = match try!(self.threads()) {
+            Some(summaries) => summaries,
+            None => self.threads_cache.lock().unwrap().clone()
+        };
+        Ok(self.remove_absent(&live))
+    }
+
+    /// Removes the cached threads that are not in the list of live threads.
+    fn remove_absent(&self, live: &[ThreadSummary]) -> Vec<u64> {
+        let live: HashSet<u64> = live.iter().map(|t| t.no).collect();
+        let mut cache = self.thread_cache.write().unwrap();
+        let mut dead: Vec<u64> = cache.threads.keys()
+            .filter(|no| !live.contains(no))
+            .cloned()
+            .collect();
+        dead.sort();
+        for &no in &dead {
+            cache.remove(no);
+        }
+        dead
+    }
+
+    /// Get the OP of a thread that you know the thread number of. If the
+    /// thread is in the cache and younger than the TTL of the cache, the
+    /// cached OP is returned without cloning the rest of the thread.
+    /// Otherwise the thread is fetched or updated like `get_thread`, which
+    /// refreshes the cache.
+    ///
+    /// Returns `Error::ThreadNotFound` if the thread does not exist or has
+    /// been pruned.
+    pub fn get_op(&self, thread_no: u64) -> ::Result<::Post> {
+        // Only removing an expired thread takes the write lock.
+        let expired = {
+            let cache = self.thread_cache.read().unwrap();
+            match cache.get(thread_no) {
+                Some(t) if t.is_expired() => true,
+                Some(t) if !cache.needs_update(t) => {
+                    return Ok(t.op().clone())
+                },
+                _ => false
+            }
+        };
+        if expired {
+            self.thread_cache.write().unwrap().remove(thread_no);
+            return Err(::Error::ThreadNotFound(thread_no))
+        }
+        let thread = try!(self.get_thread(thread_no));
+        Ok(thread.op().clone())
+    }
+
+    /// Checks whether a thread exists without downloading it, with a HEAD
+    /// request to the thread json. Falls back to a GET whose body is
+    /// discarded if the server doesn't allow HEAD. The cache isn't touched.
+    pub fn thread_exists(&self, thread_no: u64) -> ::Result<bool> {
+        let url = self.thread_url(thread_no);
+        let sender = self.sender();
+        let mut status = try!(sender.head(&url)).status();
+        if status == StatusCode::METHOD_NOT_ALLOWED {
+            // Dropping the response discards the body.
+            status = try!(sender.get(&url, None)).status();
+        }
+
+        match status {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(::Error::UnexpectedStatus(status))
+        }
     }
 
     /// Get a `Thread` that you know the thread number of. First checks that
-    /// the thread is in the cache, and updates it if it is. If not, then
-    /// makes a request, adds the created struct to the cache, and returns
-    /// the thread.
-    pub fn get_thread(& self, thread_no: u64) -> ::Result<::Thread> {
-        if self.thread_cache.lock().unwrap().contains(thread_no) {
-            try!(self.thread_cache.lock().unwrap().threads
-                .get_mut(&thread_no)
-                .unwrap()
-                .update());
-            return Ok(self.thread_cache.lock().unwrap()
-                      .get(thread_no).unwrap().clone())
-        }
-
-        let mut res = try!(self.client.lock().unwrap().get(
-                &format!("https://a.4cdn.org/{}/thread/{}.json",
-                         self.name, thread_no), None));
-        let mut buf = String::new();
-        try!(res.read_to_string(&mut buf));
-        let deserializer: ::ThreadDeserializer = try!(
-            ::serde_json::from_str(&buf));
-        let thread = ::Thread::from_deserializer(
-            deserializer, &self.name, self.client.clone());
-        self.thread_cache.lock().unwrap().insert(thread.clone());
-
-        Ok(thread)
+    /// the thread is in the cache, and updates it if it is older than the
+    /// TTL of the cache. If not, then makes a request, adds the created
+    /// struct to the cache, and returns the thread.
+    ///
+    /// Returns `Error::ThreadNotFound` if the thread does not exist or has
+    /// been pruned, in which case it is also removed from the cache.
+    pub fn get_thread(&self, thread_no: u64) -> ::Result<::Thread> {
+        self.get_thread_cached(thread_no).map(|(thread, _)| thread)
+    }
+
+    /// Get a `Thread` like `get_thread`, along with whether it was served
+    /// from the cache.
+    pub fn get_thread_cached(&self, thread_no: u64)
+        -> ::Result<(::Thread, CacheOutcome)> {
+        // A thread that doesn't need an update is served under the read
+        // lock, so that concurrent lookups don't wait on each other. For any
+        // other only what the update needs is taken, so that the cache isn't
+        // locked during the request. It stays cached meanwhile, so
+        // concurrent lookups still find it.
+        match self.cached_thread(thread_no) {
+            Some(Cached::Fresh(thread)) => return Ok((thread, CacheOutcome::Hit)),
+            Some(Cached::Expired) => {
+                self.thread_cache.write().unwrap().remove(thread_no);
+                return Err(::Error::ThreadNotFound(thread_no))
+            },
+            Some(Cached::Stale(request)) => {
+                // On error the cached copy is left as it was.
+                let response = try!(request.send());
+                if let Some(res) = self.write_back(thread_no, response) {
+                    return res.map(|thread| (thread, CacheOutcome::HitUpdated))
+                }
+            },
+            None => {}
+        }
+
+        let mut res = try!(self.sender().get(&self.thread_url(thread_no),
+                                             None));
+        let buf = try!(::read_text(&mut res));
+        self.thread_received(thread_no, res.status(), &buf)
+            .map(|thread| (thread, CacheOutcome::Miss))
+    }
+
+    /// Looks up a thread in the cache, see `lookup`.
+    pub(crate) fn cached_thread(&self, thread_no: u64) -> Option<Cached> {
+        let cache = self.thread_cache.read().unwrap();
+        cache.get(thread_no).map(|thread| lookup(&cache, thread))
+    }
+
+    /// Applies the response to the update of a cached thread and clones the
+    /// result. Returns `None` if the thread was removed meanwhile, in which
+    /// case it should be fetched anew.
+    pub(crate) fn write_back(&self,
+                             thread_no: u64,
+                             response: ::thread::UpdateResponse)
+        -> Option<::Result<::Thread>> {
+        let mut cache = self.thread_cache.write().unwrap();
+        cache.apply(thread_no, response)?;
+        Some(cache.get(thread_no).cloned()
+             .ok_or(::Error::ThreadNotFound(thread_no)))
+    }
+
+    /// Get the url of a thread.
+    pub(crate) fn thread_url(&self, thread_no: u64) -> String {
+        self.api_url(&format!("/{}/thread/{}.json", self.name, thread_no))
+    }
+
+    /// Handles the response to a request for a thread that isn't cached, and
+    /// caches it.
+    pub(crate) fn thread_received(&self,
+                                  thread_no: u64,
+                                  status: StatusCode,
+                                  buf: &str) -> ::Result<::Thread> {
+        match status {
+            StatusCode::OK => {
+                let deserializer: ::ThreadDeserializer = try!(
+                    ::serde_json::from_str(buf));
+                if deserializer.posts.is_empty() {
+                    return Err(::Error::ThreadNotFound(thread_no))
+                }
+                let thread = ::Thread::from_deserializer(
+                    deserializer, &self.name, self.client.clone());
+                self.thread_cache.write().unwrap().replace(thread.clone());
+                Ok(thread)
+            },
+            StatusCode::NOT_FOUND => {
+                // A stale copy may have been cached meanwhile.
+                self.thread_cache.write().unwrap().remove(thread_no);
+                Err(::Error::ThreadNotFound(thread_no))
+            },
+            status => Err(::Error::UnexpectedStatus(status))
+        }
     }
 }
 
+/// A thread found in the cache, see `lookup`.
+pub(crate) enum Cached {
+    // The thread doesn't need an update, so it is served as it is.
+    Fresh(::Thread),
+    // The thread needs an update, which is made with the request.
+    Stale(::thread::UpdateRequest),
+    // The thread expired, so it should be removed.
+    Expired
+}
+
+/// Decides how to serve a cached thread. Only a fresh thread is cloned.
+fn lookup(cache: &::ThreadCache, thread: &::Thread) -> Cached {
+    if thread.expired {
+        Cached::Expired
+    } else if !cache.needs_update(thread) {
+        Cached::Fresh(thread.clone())
+    } else {
+        // Not expired, so there is a request.
+        thread.update_request().map_or(Cached::Expired, Cached::Stale)
+    }
+}
+
+/// A `MultiBoard` searches the thread caches of several boards at once.
+/// The boards should share a client, so that the rate limit applies across
+/// all of them.
+#[derive(Clone, Debug)]
+pub struct MultiBoard {
+    boards: Vec<Board>
+}
+
+#[allow(deprecated)]
+impl MultiBoard {
+    /// Creates a `MultiBoard` of the boards with the given names, sharing
+    /// the client.
+    pub fn new(client: Arc<Mutex<::Client>>, names: &[&str])
+        -> ::Result<MultiBoard> {
+        let mut boards = Vec::new();
+        for name in names {
+            boards.push(try!(Board::new(client.clone(), name)));
+        }
+        Ok(MultiBoard { boards })
+    }
+
+    /// Creates a `MultiBoard` of existing boards.
+    pub fn from_boards(boards: Vec<Board>) -> MultiBoard {
+        MultiBoard { boards }
+    }
+
+    /// Get the boards that are searched.
+    pub fn boards(&self) -> &[Board] {
+        &self.boards
+    }
+
+    /// Get the board with the given name, if it is searched.
+    pub fn board(&self, name: &str) -> Option<&Board> {
+        self.boards.iter().find(|b| b.name == name)
+    }
+
+    /// Finds threads in the cache of every board like
+    /// `Board::find_cached`. The boards are searched one after the other.
+    pub fn find_cached(&self, query: &str) -> ::Result<MultiSearch> {
+        self.find_cached_in(query, ::SearchFields::all())
+    }
+
+    /// Finds threads in the cache of every board like
+    /// `Board::find_cached_in`.
+    ///
+    /// An error updating the threads of one board doesn't stop the search,
+    /// it is returned with the name of the board instead. Only an invalid
+    /// query fails the whole search.
+    pub fn find_cached_in(&self, query: &str, fields: ::SearchFields)
+        -> ::Result<MultiSearch> {
+        // Fail early rather than once per board.
+        try!(build_regex(query));
+
+        let mut search = MultiSearch::default();
+        for board in &self.boards {
+            match board.find_cached_in(query, fields) {
+                Ok(threads) => {
+                    search.threads.extend(
+                        threads.into_iter().map(|t| (board.name.clone(), t)));
+                },
+                Err(e) => search.errors.push((board.name.clone(), e))
+            }
+        }
+        Ok(search)
+    }
+}
+
+/// Where `Board::get_thread_cached` got a thread from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheOutcome {
+    // The thread was in the cache and younger than its TTL.
+    Hit,
+    // The thread was in the cache, but had to be updated first.
+    HitUpdated,
+    // The thread wasn't in the cache and was fetched.
+    Miss
+}
+
+/// The outcome of `Board::refresh_all`.
+#[derive(Debug, Default)]
+pub struct RefreshSummary {
+    // Number of threads that changed.
+    pub updated: usize,
+    // Number of threads that didn't change.
+    pub unchanged: usize,
+    // Number of threads that expired and were removed from the cache.
+    pub expired: usize,
+    // Numbers of the threads that failed to update, with the reason.
+    pub failed: Vec<(u64, ::Error)>
+}
+
+/// The results of a `MultiBoard` search.
+#[derive(Debug, Default)]
+pub struct MultiSearch {
+    // Matching threads tagged with the name of their board.
+    pub threads: Vec<(String, ::Thread)>,
+    // Errors of the boards that could not be searched.
+    pub errors: Vec<(String, ::Error)>
+}
+
 /// A `Catalog` contains the information from the 4chan catalog API. Rather
 /// than creating `Thread` structs, it contains `Post` structs which represent
 /// the thread's topic (aka. OP). If you wish to access the implementation of
 /// a `Thread` then use `Board::get_thread` or `Board::find_cached`.
-#[derive(Clone, Debug, Deserialize)]
+///
+/// A `Catalog` can be serialized to be saved and deserialized again. Its
+/// `pages` serialize to the shape of the catalog endpoint.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Catalog {
     pub pages: Vec<Page>
 }
 
+#[allow(deprecated)]
 impl Catalog {
+    /// Parses the catalog endpoint's body, a json array of pages. A leading
+    /// byte order mark is ignored.
+    fn from_pages_json(json: &str) -> ::Result<Catalog> {
+        let pages: Vec<Page> = try!(
+            ::serde_json::from_str(json.trim_start_matches('\u{feff}')));
+        Ok(Catalog { pages })
+    }
+
+    /// Get a page of the catalog. Pages are numbered from 0 here, whereas
+    /// 4chan numbers them from 1, so `page(0)` is the first page.
+    pub fn page(&self, n: u8) -> Option<&Page> {
+        self.pages.iter().find(|p| p.page as usize == n as usize + 1)
+    }
+
+    pub fn num_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Iterates over the topics of every page in order, without collecting
+    /// them like `topics` does. Pages are only walked as far as the iterator
+    /// is advanced.
+    pub fn iter_topics(&self) -> Topics<'_> {
+        Topics { pages: self.pages.iter(), topics: None }
+    }
+
+    /// Same as `iter_topics`.
+    pub fn topics_iter(&self) -> Topics<'_> {
+        self.iter_topics()
+    }
+
+    /// Get the first `n` topics, in order. Only walks the pages it needs,
+    /// so `take_topics(15)` only looks at the first page of most boards.
+    pub fn take_topics(&self, n: usize) -> Vec<&::Post> {
+        self.iter_topics().take(n).collect()
+    }
+
+    /// Get every topic of every page in order. This allocates a `Vec` as
+    /// large as the whole catalog; prefer `iter_topics` or `take_topics` if
+    /// you don't need all of them at once.
     pub fn topics(&self) -> Vec<&::Post> {
         self.pages.iter()
             .fold(Vec::new(), |mut topics, p| {
@@ -158,16 +865,162 @@ impl Catalog {
             })
     }
 
+    /// Get the topics ordered by when their thread was last modified, most
+    /// recent first. Topics without a modification time come last. Ties
+    /// keep their catalog order.
+    pub fn topics_by_bump(&self) -> Vec<&::Post> {
+        let mut topics = self.topics();
+        // The sort is stable, and a missing time is 0.
+        topics.sort_by_key(|t| Reverse(t.last_modified));
+        topics
+    }
+
+    /// Get the `n` topics with the most replies, most first. Topics without
+    /// a reply count come last, and ties keep their catalog order. Only the
+    /// `n` best topics are kept while walking the catalog.
+    pub fn busiest(&self, n: usize) -> Vec<&::Post> {
+        self.top_topics(n, |t| t.reply_count())
+    }
+
+    /// Get the `n` topics with the most images, like `busiest`.
+    pub fn busiest_images(&self, n: usize) -> Vec<&::Post> {
+        self.top_topics(n, |t| t.image_count())
+    }
+
+    fn top_topics<F>(&self, n: usize, key: F) -> Vec<&::Post>
+        where F: Fn(&::Post) -> Option<u32>
+    {
+        let topics = self.topics();
+
+        // A min-heap of the best topics so far, where the worst one is
+        // popped. Among equal keys, the later topic is worse. It never holds
+        // more than one topic over `n`, nor more than every topic.
+        let capacity = cmp::min(n.saturating_add(1), topics.len() + 1);
+        let mut heap = BinaryHeap::with_capacity(capacity);
+        for (i, topic) in topics.iter().enumerate() {
+            heap.push(Reverse((key(topic), Reverse(i))));
+            if heap.len() > n {
+                heap.pop();
+            }
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((_, Reverse(i)))| topics[i])
+            .collect()
+    }
+
+    /// Get the stickied topics.
+    pub fn pinned(&self) -> Vec<&::Post> {
+        self.topics()
+            .into_iter()
+            .filter(|t| t.is_sticky())
+            .collect()
+    }
+
+    /// Get the topics of the threads that are neither closed nor archived.
+    pub fn active(&self) -> Vec<&::Post> {
+        self.topics()
+            .into_iter()
+            .filter(|t| !t.is_closed() && !t.is_archived())
+            .collect()
+    }
+
+    /// Compares the catalog with a newer one. Threads are bumped if their
+    /// `last_modified` changed.
+    pub fn diff(&self, newer: &Catalog) -> CatalogDiff {
+        let old: HashMap<u64, i64> = self.iter_topics()
+            .map(|t| (t.no, t.last_modified))
+            .collect();
+        let new: HashSet<u64> = newer.iter_topics().map(|t| t.no).collect();
+
+        let mut diff = CatalogDiff::default();
+        for topic in newer {
+            match old.get(&topic.no) {
+                None => diff.new_threads.push(topic.no),
+                Some(&modified) if modified != topic.last_modified => {
+                    diff.bumped.push(topic.no)
+                },
+                Some(_) => ()
+            }
+        }
+        diff.gone = self.iter_topics()
+            .map(|t| t.no)
+            .filter(|no| !new.contains(no))
+            .collect();
+        diff
+    }
+
+    /// Exports the topics as CSV, one row per topic after a header row, with
+    /// the columns `no`, `page`, `subject`, `replies`, `images`,
+    /// `last_modified`, `sticky` and `closed`. The subject is plain text,
+    /// `last_modified` is a unix timestamp and the flags are `true` or
+    /// `false`.
+    pub fn to_csv(&self) -> String {
+        let mut writer = ::csv::Writer::from_writer(Vec::new());
+        // Writing into a `Vec` can't fail.
+        writer.write_record(["no", "page", "subject", "replies", "images",
+                              "last_modified", "sticky", "closed"])
+            .unwrap();
+        for page in &self.pages {
+            for topic in &page.topics {
+                writer.write_record(&[
+                    topic.no.to_string(),
+                    page.number().to_string(),
+                    topic.subject_text().unwrap_or_default(),
+                    topic.replies.to_string(),
+                    topic.images.to_string(),
+                    topic.last_modified.to_string(),
+                    topic.is_sticky().to_string(),
+                    topic.is_closed().to_string()
+                ]).unwrap();
+            }
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    /// Finds the topics that contain the query string in one of their name,
+    /// comment, subject, or filename. The search is case insensitive and
+    /// uses unicode.
+    ///
+    /// The query is a regex. Use `find_literal` to search for a plain string.
     pub fn find(&self, query: &str) -> ::Result<Option<Vec<&::Post>>> {
-        let mut regex_builder = RegexBuilder::new(query);
-        let regex = try!(regex_builder
-                         .case_insensitive(true)
-                         .unicode(true)
-                         .build());
+        self.find_in(query, ::SearchFields::all())
+    }
+
+    /// Finds topics like `find`, but the query is matched literally rather
+    /// than as a regex.
+    pub fn find_literal(&self, query: &str) -> ::Result<Option<Vec<&::Post>>> {
+        self.find(&escape(query))
+    }
+
+    /// Finds topics like `find`, but only those whose thread was modified
+    /// after `since`. Topics without a modification time are excluded.
+    pub fn find_since(&self, query: &str, since: DateTime<UTC>)
+        -> ::Result<Option<Vec<&::Post>>> {
+        let topics: Vec<&::Post> = try!(self.find(query))
+            .unwrap_or_else(Vec::new)
+            .into_iter()
+            .filter(|t| t.last_modified != 0 &&
+                    t.last_modified > since.timestamp())
+            .collect();
+
+        if topics.is_empty() {
+            return Ok(None)
+        }
+
+        Ok(Some(topics))
+    }
+
+    /// Finds topics like `find`, but only matches the query against the
+    /// given fields.
+    pub fn find_in(&self, query: &str, fields: ::SearchFields)
+        -> ::Result<Option<Vec<&::Post>>> {
+        let regex = try!(build_regex(query));
 
         let topics: Vec<&::Post> = self.topics()
             .into_iter()
-            .filter(|&t| t.is_match(&regex))
+            .filter(|&t| t.is_match_in(&regex, fields))
             .collect();
 
         if topics.is_empty() {
@@ -178,11 +1031,710 @@ impl Catalog {
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// Builds the case insensitive unicode regex used by searches.
+#[allow(deprecated)]
+fn build_regex(query: &str) -> ::Result<Regex> {
+    let mut regex_builder = RegexBuilder::new(query);
+    Ok(try!(regex_builder
+            .case_insensitive(true)
+            .unicode(true)
+            .build()))
+}
+
+/// What changed between two catalogs, see `Catalog::diff`. The thread
+/// numbers are in catalog order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CatalogDiff {
+    // Threads that are not in the older catalog.
+    pub new_threads: Vec<u64>,
+    // Threads that were modified since the older catalog.
+    pub bumped: Vec<u64>,
+    // Threads of the older catalog that are not in the newer one.
+    pub gone: Vec<u64>
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Page {
     page: u8,
     // Rather than `Thread` objects, pages create a `Post` representing the
     // thread's topic (aka. OP).
-    #[serde(rename="threads")]
+    #[serde(rename="threads", deserialize_with="deserialize_topics")]
     pub topics: Vec<::Post>
 }
+
+/// Deserializes the topics of a page, marking them as previews since the
+/// catalog may truncate their comments.
+#[allow(deprecated)]
+fn deserialize_topics<'de, D>(deserializer: D)
+    -> ::std::result::Result<Vec<::Post>, D::Error>
+    where D: ::serde::Deserializer<'de>
+{
+    let mut topics: Vec<::Post> =
+        try!(::serde::Deserialize::deserialize(deserializer));
+    for topic in &mut topics {
+        topic.preview = true;
+    }
+    Ok(topics)
+}
+
+impl Page {
+    /// The number of the page, starting from 1.
+    pub fn number(&self) -> u8 {
+        self.page
+    }
+}
+
+/// An iterator over the topics of a `Catalog`, page by page.
+pub struct Topics<'a> {
+    pages: ::std::slice::Iter<'a, Page>,
+    topics: Option<::std::slice::Iter<'a, ::Post>>
+}
+
+impl<'a> Iterator for Topics<'a> {
+    type Item = &'a ::Post;
+
+    fn next(&mut self) -> Option<&'a ::Post> {
+        loop {
+            if let Some(topic) = self.topics.as_mut().and_then(|t| t.next()) {
+                return Some(topic)
+            }
+            match self.pages.next() {
+                Some(page) => self.topics = Some(page.topics.iter()),
+                None => return None
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Catalog {
+    type Item = &'a ::Post;
+    type IntoIter = Topics<'a>;
+
+    fn into_iter(self) -> Topics<'a> {
+        self.iter_topics()
+    }
+}
+
+/// A `ThreadSummary` is an entry of the threads.json endpoint, which lists
+/// every live thread on a board.
+#[derive(Clone, Debug)]
+pub struct ThreadSummary {
+    pub no: u64,
+    pub last_modified: DateTime<UTC>,
+    pub replies: u32,
+    pub page: u8
+}
+
+// The threads.json endpoint is an array of pages, each containing a list of
+// abridged threads.
+#[derive(Deserialize)]
+struct ThreadsPage {
+    page: u8,
+    threads: Vec<ThreadsEntry>
+}
+
+#[derive(Deserialize)]
+struct ThreadsEntry {
+    no: u64,
+    last_modified: i64,
+    #[serde(default)]
+    replies: u32
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use chrono::{DateTime, NaiveDateTime, UTC};
+
+    fn board(name: &str) -> ::Board {
+        ::Board::new_unchecked(Arc::new(Mutex::new(::Client::new().unwrap())),
+                               name)
+    }
+
+    fn topic(no: u64, sub: &str) -> ::Post {
+        let mut post: ::Post = ::serde_json::from_str(&format!(
+            r#"{{"no": {}, "resto": 0, "now": "", "time": 0}}"#, no))
+            .unwrap();
+        post.sub = sub.to_string();
+        post
+    }
+
+    #[test]
+    fn find_cached_excludes_expired() {
+        let g = board("g");
+        let mut thread = ::Thread::from_topic(
+            topic(1, "installgentoo"), "g", g.client.clone());
+        thread.expired = true;
+        g.thread_cache.write().unwrap().insert(thread);
+
+        let found = g.find_cached("installgentoo").unwrap();
+        assert!(found.is_empty());
+        assert!(!g.thread_cache.read().unwrap().contains(1));
+    }
+
+    #[test]
+    fn find_cached_keeps_updating_threads() {
+        let g = board("g");
+        let g2 = g.clone();
+        let (base, server) = ::mock::serve_with(1, move |_| {
+            // The thread is still cached while it updates. A concurrent
+            // catalog refresh that reinserts it must not win.
+            {
+                let mut cache = g2.thread_cache.write().unwrap();
+                assert!(cache.contains(1));
+                let stub = ::Thread::from_topic(
+                    topic(1, "stale"), "g", g2.client.clone());
+                cache.remove(1);
+                cache.insert(stub);
+            }
+            ::mock::response("200 OK", r#"{"posts": [{"no": 1, "resto": 0,
+                "now": "", "time": 0, "sub": "installgentoo"}, {"no": 2,
+                "resto": 1, "now": "", "time": 0}]}"#)
+        });
+
+        g.client.lock().unwrap().set_api_base(&base);
+        g.thread_cache.write().unwrap().insert(::Thread::from_topic(
+            topic(1, "installgentoo"), "g", g.client.clone()));
+
+        let found = g.find_cached("installgentoo").unwrap();
+        server.join().unwrap();
+        assert_eq!(1, found.len());
+        assert_eq!(2, found[0].posts().len());
+        let cache = g.thread_cache.read().unwrap();
+        let cached = cache.get(1).unwrap();
+        assert_eq!(2, cached.posts().len());
+        assert_eq!("installgentoo", cached.op().sub);
+    }
+
+    #[test]
+    fn get_thread_not_found() {
+        let g = board("g");
+        let mut thread = ::Thread::from_topic(
+            topic(1, ""), "g", g.client.clone());
+        thread.expired = true;
+        g.thread_cache.write().unwrap().insert(thread);
+
+        match g.get_thread(1) {
+            Err(::Error::ThreadNotFound(1)) => (),
+            res => panic!("Expected ThreadNotFound, got {:?}", res)
+        }
+        assert!(!g.thread_cache.read().unwrap().contains(1));
+    }
+
+    #[test]
+    fn get_thread_not_found_on_404() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("404 Not Found", "")]);
+        let g = board("g");
+        g.client.lock().unwrap().set_api_base(&base);
+        // The cached thread is stale, so it is updated and 404s.
+        g.thread_cache.write().unwrap().insert(::Thread::from_topic(
+            topic(1, ""), "g", g.client.clone()));
+
+        match g.get_thread(1) {
+            Err(::Error::ThreadNotFound(1)) => (),
+            res => panic!("Expected ThreadNotFound, got {:?}", res)
+        }
+        server.join().unwrap();
+        assert!(!g.thread_cache.read().unwrap().contains(1));
+    }
+
+    #[test]
+    fn get_thread_cached_hit() {
+        let g = board("g");
+        let deserializer = ::ThreadDeserializer { posts: vec![topic(1, "")] };
+        {
+            let mut cache = g.thread_cache.write().unwrap();
+            cache.set_ttl(::std::time::Duration::from_secs(60));
+            cache.insert(::Thread::from_deserializer(
+                deserializer, "g", g.client.clone()));
+        }
+
+        // No request is made, or it would fail.
+        g.client.lock().unwrap().set_api_base("http://127.0.0.1:1");
+        let (thread, outcome) = g.get_thread_cached(1).unwrap();
+        assert_eq!(1, thread.op().no);
+        assert_eq!(::CacheOutcome::Hit, outcome);
+    }
+
+    #[test]
+    fn get_thread_cached_keeps_newer_copy() {
+        let g = board("g");
+        let g2 = g.clone();
+        let (base, server) = ::mock::serve_with(1, move |_| {
+            // A copy modified later than the response is cached meanwhile.
+            let mut op = topic(1, "newer");
+            op.last_modified = 4102444800;
+            let deserializer = ::ThreadDeserializer { posts: vec![op] };
+            g2.thread_cache.write().unwrap().replace(
+                ::Thread::from_deserializer(deserializer, "g",
+                                            g2.client.clone()));
+            ::mock::response("200 OK", r#"{"posts": [{"no": 1, "resto": 0,
+                "now": "", "time": 0, "sub": "older"}]}"#)
+        });
+
+        g.client.lock().unwrap().set_api_base(&base);
+        g.thread_cache.write().unwrap().insert(::Thread::from_topic(
+            topic(1, "stub"), "g", g.client.clone()));
+
+        let (thread, outcome) = g.get_thread_cached(1).unwrap();
+        server.join().unwrap();
+        assert_eq!(::CacheOutcome::HitUpdated, outcome);
+        assert_eq!("newer", thread.op().sub);
+        assert_eq!("newer", g.thread_cache.read().unwrap()
+                   .get(1).unwrap().op().sub);
+    }
+
+    #[test]
+    fn get_thread_without_posts() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("200 OK", r#"{"posts": []}"#)]);
+        let g = board("g");
+        g.client.lock().unwrap().set_api_base(&base);
+
+        match g.get_thread(1) {
+            Err(::Error::ThreadNotFound(1)) => (),
+            res => panic!("Expected ThreadNotFound, got {:?}", res)
+        }
+        server.join().unwrap();
+        assert!(!g.thread_cache.read().unwrap().contains(1));
+    }
+
+    #[test]
+    fn get_thread_invalid_utf8() {
+        let body: &[u8] = b"{\"posts\": [{\"no\": 1, \"resto\": 0, \
+            \"now\": \"\", \"time\": 0, \"com\": \"bad \xff byte\"}]}";
+        let mut res = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                              body.len()).into_bytes();
+        res.extend_from_slice(body);
+        let (base, server) = ::mock::serve(vec![res]);
+
+        let g = board("g");
+        g.client.lock().unwrap().set_api_base(&base);
+        let thread = g.get_thread(1).unwrap();
+        server.join().unwrap();
+        assert_eq!("bad \u{fffd} byte", thread.op().comment_text().unwrap());
+    }
+
+    #[test]
+    fn get_thread_cached_shares_read_lock() {
+        let g = board("g");
+        let deserializer = ::ThreadDeserializer { posts: vec![topic(1, "")] };
+        {
+            let mut cache = g.thread_cache.write().unwrap();
+            cache.set_ttl(::std::time::Duration::from_secs(60));
+            cache.insert(::Thread::from_deserializer(
+                deserializer, "g", g.client.clone()));
+        }
+
+        // Lookups of fresh threads complete while another reader holds the
+        // cache, which they couldn't if they needed the write lock.
+        let _reader = g.thread_cache.read().unwrap();
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        for _ in 0..4 {
+            let (g, tx) = (g.clone(), tx.clone());
+            thread::spawn(move || {
+                tx.send(g.get_thread_cached(1).map(|(_, o)| o).ok()).unwrap();
+            });
+        }
+        for _ in 0..4 {
+            let outcome = rx.recv_timeout(::std::time::Duration::from_secs(5))
+                .expect("Lookup blocked behind a reader");
+            assert_eq!(Some(::CacheOutcome::Hit), outcome);
+        }
+    }
+
+    #[test]
+    fn get_thread_cached_while_client_waits() {
+        let g = board("g");
+        let deserializer = ::ThreadDeserializer { posts: vec![topic(1, "")] };
+        {
+            let mut cache = g.thread_cache.write().unwrap();
+            cache.set_ttl(::std::time::Duration::from_secs(60));
+            cache.insert(::Thread::from_deserializer(
+                deserializer, "g", g.client.clone()));
+        }
+
+        // A direct call on the locked client holds it while it waits. A fresh
+        // thread is still served from the cache meanwhile.
+        let _client = g.client.lock().unwrap();
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let g2 = g.clone();
+        thread::spawn(move || {
+            tx.send(g2.get_thread_cached(1).map(|(_, o)| o).ok()).unwrap();
+        });
+        let outcome = rx.recv_timeout(::std::time::Duration::from_secs(5))
+            .expect("Lookup blocked behind the client");
+        assert_eq!(Some(::CacheOutcome::Hit), outcome);
+    }
+
+    #[test]
+    fn requests_wait_without_the_client_lock() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("404 Not Found", ""),
+            ::mock::response("404 Not Found", "")]);
+        let g = board("g");
+        {
+            let mut client = g.client.lock().unwrap();
+            client.set_api_base(&base);
+            client.set_min_interval(Duration::from_secs(2));
+        }
+        assert!(!g.thread_exists(1).unwrap());
+
+        // The second request waits for its slot without holding the lock.
+        let g2 = g.clone();
+        let waiting = thread::spawn(move || g2.thread_exists(2).unwrap());
+        thread::sleep(Duration::from_millis(200));
+        let start = Instant::now();
+        drop(g.client.lock().unwrap());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(!waiting.join().unwrap());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn multi_board_find_cached() {
+        let (g, a) = (board("g"), board("a"));
+        for b in &[&g, &a] {
+            let mut thread = ::Thread::from_topic(
+                topic(1, "general"), &b.name, b.client.clone());
+            thread.expired = true;
+            b.thread_cache.write().unwrap().insert(thread);
+        }
+        let multi = ::MultiBoard::from_boards(vec![g, a]);
+
+        let search = multi.find_cached("general").unwrap();
+        assert!(search.threads.is_empty());
+        assert!(search.errors.is_empty());
+        assert!(multi.board("a").is_some());
+        assert!(multi.board("v").is_none());
+
+        match multi.find_cached("(") {
+            Err(::Error::InvalidQuery(_)) => (),
+            res => panic!("Expected InvalidQuery, got {:?}", res)
+        }
+    }
+
+    #[test]
+    fn threads_last_modified_from_server() {
+        // Serves a single response with a fixed Last-Modified.
+        let (base, server) = ::mock::serve(vec![concat!(
+            "HTTP/1.1 200 OK\r\n",
+            "Last-Modified: Sat, 15 Apr 2017 01:03:25 GMT\r\n",
+            "Content-Length: 2\r\n",
+            "Connection: close\r\n\r\n",
+            "[]")]);
+
+        let g = board("g");
+        g.client.lock().unwrap().set_api_base(&base);
+        assert!(g.threads().unwrap().unwrap().is_empty());
+        server.join().unwrap();
+
+        assert_eq!("2017-04-15 01:03:25 UTC",
+                   g.threads_last_modified.lock().unwrap().unwrap()
+                       .to_string());
+    }
+
+    #[test]
+    fn catalog_etag_not_modified() {
+        // Answers 304 only if the request has the expected If-None-Match.
+        let (base, server) = ::mock::serve_with(1, |req| {
+            if req.to_lowercase().contains("if-none-match: \"abc\"\r\n") {
+                ::mock::response("304 Not Modified", "")
+            } else {
+                ::mock::response("200 OK", "[]")
+            }
+        });
+
+        let g = board("g");
+        {
+            let mut client = g.client.lock().unwrap();
+            client.set_api_base(&base);
+            client.set_validator(::Validator::ETag);
+        }
+        *g.catalog_etag.lock().unwrap() = Some("\"abc\"".to_string());
+        assert!(g.catalog().unwrap().is_none());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn watch_catalog() {
+        let (base, server) = ::mock::serve(vec![::mock::response("200 OK",
+            r#"[{"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0, "sub": "rust"},
+                {"no": 2, "resto": 0, "now": "", "time": 0, "sub": "go"}
+            ]}]"#)]);
+
+        let g = board("g");
+        g.client.lock().unwrap().set_api_base(&base);
+        let mut notified = Vec::new();
+        let interval = ::std::time::Duration::from_secs(0);
+        g.watch_catalog("rust", interval, |topics| {
+            notified.extend(topics.iter().map(|t| t.no));
+            ::WatchControl::Stop
+        }).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(vec![1], notified);
+    }
+
+    #[test]
+    fn catalog_page_uses_cache() {
+        let g = board("g");
+        *g.catalog_cache.lock().unwrap() = Some(::serde_json::from_str(
+            r#"{"pages": [{"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0}
+            ]}]}"#).unwrap());
+
+        // No request is made, or it would fail against the real API.
+        g.client.lock().unwrap().set_api_base("http://127.0.0.1:1");
+        assert_eq!(1, g.catalog_page(0).unwrap().unwrap().topics[0].no);
+        assert!(g.catalog_page(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn thread_exists() {
+        // Answers HEAD with 405, then GET with 404.
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("405 Method Not Allowed", ""),
+            ::mock::response("404 Not Found", "")]);
+
+        let g = board("g");
+        g.client.lock().unwrap().set_api_base(&base);
+        assert!(!g.thread_exists(1).unwrap());
+        let methods = server.join().unwrap().iter()
+            .map(|req| req.split(' ').next().unwrap().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["HEAD", "GET"], methods);
+    }
+
+    #[test]
+    fn remove_absent() {
+        let g = board("g");
+        for no in 1..4 {
+            g.thread_cache.write().unwrap().insert(::Thread::from_topic(
+                topic(no, ""), "g", g.client.clone()));
+        }
+        let live = vec![::ThreadSummary {
+            no: 2,
+            last_modified: UTC::now(),
+            replies: 0,
+            page: 1
+        }];
+
+        assert_eq!(3, g.cached_threads().len());
+        assert_eq!(vec![1, 3], g.remove_absent(&live));
+        let cache = g.thread_cache.read().unwrap();
+        assert_eq!(1, cache.len());
+        assert!(cache.contains(2));
+    }
+
+    #[test]
+    fn get_op_not_found() {
+        let g = board("g");
+        let mut thread = ::Thread::from_topic(
+            topic(1, ""), "g", g.client.clone());
+        thread.expired = true;
+        g.thread_cache.write().unwrap().insert(thread);
+
+        match g.get_op(1) {
+            Err(::Error::ThreadNotFound(1)) => (),
+            res => panic!("Expected ThreadNotFound, got {:?}", res)
+        }
+        assert!(!g.thread_cache.read().unwrap().contains(1));
+    }
+
+    #[test]
+    fn compile_query() {
+        let regex = ::Board::compile_query("gentoo").unwrap();
+        assert!(topic(1, "Install Gentoo").is_match(&regex));
+        assert!(!topic(2, "Install Arch").is_match(&regex));
+    }
+
+    #[test]
+    fn find_invalid_query() {
+        let catalog = ::Catalog { pages: Vec::new() };
+        match catalog.find("(") {
+            Err(::Error::InvalidQuery(_)) => (),
+            res => panic!("Expected InvalidQuery, got {:?}", res)
+        }
+        assert!(catalog.find_literal("(").unwrap().is_none());
+    }
+
+    #[test]
+    fn catalog_pages() {
+        let catalog: ::Catalog = ::serde_json::from_str(r#"{"pages": [
+            {"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0},
+                {"no": 2, "resto": 0, "now": "", "time": 0}
+            ]},
+            {"page": 2, "threads": [
+                {"no": 3, "resto": 0, "now": "", "time": 0}
+            ]}
+        ]}"#).unwrap();
+
+        assert_eq!(2, catalog.num_pages());
+        assert_eq!(1, catalog.page(0).unwrap().number());
+        assert_eq!(1, catalog.page(0).unwrap().topics[0].no);
+        assert_eq!(3, catalog.page(1).unwrap().topics[0].no);
+        assert!(catalog.page(2).is_none());
+        assert_eq!(vec![1, 2, 3],
+                   catalog.iter_topics().map(|t| t.no).collect::<Vec<u64>>());
+        assert_eq!(3, catalog.topics_iter().count());
+
+        assert_eq!(vec![1, 2],
+                   catalog.take_topics(2).iter().map(|t| t.no)
+                       .collect::<Vec<u64>>());
+        assert_eq!(3, catalog.take_topics(10).len());
+
+        let mut nos = Vec::new();
+        for topic in &catalog {
+            nos.push(topic.no);
+        }
+        assert_eq!(vec![1, 2, 3], nos);
+    }
+
+    #[test]
+    fn catalog_from_pages_json() {
+        let json = r#"[
+            {"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0}
+            ]},
+            {"page": 2, "threads": [
+                {"no": 2, "resto": 0, "now": "", "time": 0}
+            ]}
+        ]"#;
+        let catalog = ::Catalog::from_pages_json(json).unwrap();
+        assert_eq!(2, catalog.num_pages());
+        assert_eq!(vec![1, 2],
+                   catalog.iter_topics().map(|t| t.no).collect::<Vec<u64>>());
+
+        // A byte order mark is skipped.
+        assert_eq!(catalog.num_pages(), ::Catalog::from_pages_json(
+                &format!("\u{feff}{}", json)).unwrap().num_pages());
+    }
+
+    #[test]
+    fn catalog_serialize_round_trip() {
+        let catalog = ::Catalog::from_pages_json(r#"[
+            {"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0, "sub": "rust",
+                 "replies": 3, "custom_spoiler": 2}
+            ]}
+        ]"#).unwrap();
+        assert!(catalog.pages[0].topics[0].is_preview());
+
+        let json = ::serde_json::to_string(&catalog).unwrap();
+        let loaded: ::Catalog = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(catalog, loaded);
+
+        // Fields that aren't part of the API or weren't sent are left out.
+        let value: ::serde_json::Value = ::serde_json::from_str(&json)
+            .unwrap();
+        let topic = &value["pages"][0]["threads"][0];
+        assert!(topic.get("preview").is_none());
+        assert!(topic.get("country").is_none());
+
+        // The pages have the shape of the endpoint.
+        let pages = ::serde_json::to_string(&catalog.pages).unwrap();
+        assert_eq!(catalog, ::Catalog::from_pages_json(&pages).unwrap());
+    }
+
+    #[test]
+    fn catalog_busiest() {
+        let catalog = ::Catalog::from_pages_json(r#"[
+            {"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0,
+                 "replies": 5, "images": 0},
+                {"no": 2, "resto": 0, "now": "", "time": 0,
+                 "replies": 50, "images": 1},
+                {"no": 3, "resto": 0, "now": "", "time": 0,
+                 "replies": 5, "images": 9}
+            ]},
+            {"page": 2, "threads": [
+                {"no": 4, "resto": 0, "now": "", "time": 0,
+                 "replies": 7, "images": 2}
+            ]}
+        ]"#).unwrap();
+        let nos = |topics: Vec<&::Post>| {
+            topics.iter().map(|t| t.no).collect::<Vec<u64>>()
+        };
+
+        assert_eq!(vec![2, 4, 1], nos(catalog.busiest(3)));
+        assert_eq!(vec![2, 4, 1, 3], nos(catalog.busiest(10)));
+        assert_eq!(vec![2, 4, 1, 3], nos(catalog.busiest(usize::MAX)));
+        assert_eq!(vec![3, 4], nos(catalog.busiest_images(2)));
+        assert!(catalog.busiest(0).is_empty());
+    }
+
+    #[test]
+    fn catalog_diff() {
+        let older: ::Catalog = ::serde_json::from_str(r#"{"pages": [
+            {"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0,
+                 "last_modified": 100},
+                {"no": 2, "resto": 0, "now": "", "time": 0,
+                 "last_modified": 100}
+            ]}
+        ]}"#).unwrap();
+        let newer: ::Catalog = ::serde_json::from_str(r#"{"pages": [
+            {"page": 1, "threads": [
+                {"no": 3, "resto": 0, "now": "", "time": 0,
+                 "last_modified": 300},
+                {"no": 1, "resto": 0, "now": "", "time": 0,
+                 "last_modified": 200}
+            ]}
+        ]}"#).unwrap();
+
+        assert_eq!(::CatalogDiff {
+            new_threads: vec![3],
+            bumped: vec![1],
+            gone: vec![2]
+        }, older.diff(&newer));
+    }
+
+    #[test]
+    fn catalog_to_csv() {
+        let catalog: ::Catalog = ::serde_json::from_str(r#"{"pages": [
+            {"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0, "sticky": 1,
+                 "sub": "&quot;Rules&quot;, read them", "replies": 2,
+                 "images": 1, "last_modified": 100}
+            ]}
+        ]}"#).unwrap();
+
+        assert_eq!(concat!(
+            "no,page,subject,replies,images,last_modified,sticky,closed\n",
+            "1,1,\"\"\"Rules\"\", read them\",2,1,100,true,false\n"),
+            catalog.to_csv());
+    }
+
+    #[test]
+    fn catalog_topics_by_bump() {
+        let catalog: ::Catalog = ::serde_json::from_str(r#"{"pages": [
+            {"page": 1, "threads": [
+                {"no": 1, "resto": 0, "now": "", "time": 0},
+                {"no": 2, "resto": 0, "now": "", "time": 0,
+                 "last_modified": 100},
+                {"no": 3, "resto": 0, "now": "", "time": 0}
+            ]},
+            {"page": 2, "threads": [
+                {"no": 4, "resto": 0, "now": "", "time": 0,
+                 "last_modified": 200}
+            ]}
+        ]}"#).unwrap();
+
+        assert_eq!(vec![4, 2, 1, 3],
+                   catalog.topics_by_bump().iter().map(|t| t.no)
+                       .collect::<Vec<u64>>());
+        assert_eq!(1, catalog.topics()[0].no);
+
+        let since = DateTime::<UTC>::from_utc(
+            NaiveDateTime::from_timestamp(150, 0), UTC);
+        let found = catalog.find_since("", since).unwrap().unwrap();
+        assert_eq!(vec![4], found.iter().map(|t| t.no).collect::<Vec<u64>>());
+    }
+}
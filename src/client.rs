@@ -1,119 +1,1016 @@
+use std::cmp;
+use std::collections::HashMap;
 use std::io::Read;
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-use chrono::{DateTime, Duration, UTC};
-use serde_json::Value;
-use reqwest::header::{Headers, UserAgent};
+use chrono::UTC;
+#[cfg(feature = "async")]
+use futures::{future, Future, Stream};
+use reqwest::{Method, StatusCode};
+use reqwest::header::{HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+                      USER_AGENT};
+#[cfg(feature = "async")]
+use tokio_timer::Delay;
 
-static BOARDS_URL: &'static str = "https://a.4cdn.org/boards.json";
+static API_BASE: &str = "https://a.4cdn.org";
+static MEDIA_BASE: &str = "https://i.4cdn.org";
+
+/// The minimum interval between requests as dictated by the 4chan API rules.
+const DEFAULT_MIN_INTERVAL_MS: u64 = 1000;
+
+/// The number of times a request is retried on a server or connection error.
+const DEFAULT_RETRIES: u32 = 2;
+
+/// The backoff before the first retry, doubled on every following retry.
+const DEFAULT_BACKOFF_BASE_MS: u64 = 1000;
+
+/// How long every request is held back after a 429 Too Many Requests.
+const DEFAULT_RATE_LIMIT_COOLDOWN_SECS: u64 = 30;
+
+/// The number of pages of a board that boards.json doesn't give.
+const DEFAULT_PAGES: u32 = 10;
+
+/// The number of threads per page of a board that boards.json doesn't give.
+const DEFAULT_PER_PAGE: u32 = 15;
+
+/// The user agent sent with every request unless another one is configured.
+const DEFAULT_USER_AGENT: &str = concat!("clover/",
+                                         env!("CARGO_PKG_VERSION"));
 
 /// A `Client` makes all the API GET requests. All requests are throttled by
 /// a 1 second interval to comply with the 4chan API rules. Use the same client
 /// for all your boards (see examples).
+///
+/// The underlying `reqwest` client is built once with the `Client`, so every
+/// board sharing it also shares its pool of keep-alive connections.
 #[derive(Debug)]
 pub struct Client {
     reqwest_client: ::reqwest::Client,
-    all_boards: Vec<String>,
-    // List of blue boards
-    sfw_boards: Vec<String>,
-    // List of red boards
-    nsfw_boards: Vec<String>,
-    last_request: DateTime<UTC>,
+    // The async `reqwest` client of the `async` feature, built alongside.
+    #[cfg(feature = "async")]
+    async_client: ::reqwest::async::Client,
+    // List of boards, lazily fetched from boards.json.
+    boards: Option<Vec<BoardInfo>>,
+    // Maps a board name to its index in `boards`.
+    board_index: HashMap<String, usize>,
+    // When requests may be sent. Shared with the `Sender`s of the client.
+    limiter: Arc<Mutex<Limiter>>,
+    retries: u32,
+    backoff_base: Duration,
+    validator: Validator,
+    // Base urls of the JSON API and of the media files.
+    api_base: String,
+    media_base: String,
+    // Base urls of the hosts media downloads fall back to, in order.
+    media_fallbacks: Vec<String>,
 }
 
+#[allow(deprecated)]
 impl Client {
-    /// Creates a new `Client`.
+    /// Creates a new `Client` with the default configuration. No request is
+    /// made until the client is used.
     pub fn new() -> ::Result<Client> {
-        let client = try!(::reqwest::Client::new());
-
-        let last_request = UTC::now();
-        let mut res = try!(client.get(BOARDS_URL).send());
-        assert!(res.status().is_success());
-
-        let mut buf = String::new();
-        try!(res.read_to_string(&mut buf));
-
-        let v: Value = try!(::serde_json::from_str(&buf));
-        let mut sfw_boards = Vec::new();
-        let mut nsfw_boards = Vec::new();
-        let mut all_boards = Vec::new();
-
-        let boards = v["boards"].as_array().unwrap();
-        for board in boards {
-            let name = board["board"].as_str().unwrap();
-            if board["ws_board"].as_u64().unwrap() == 1 {
-                sfw_boards.push(name.to_string());
-            } else{
-                nsfw_boards.push(name.to_string());
-            }
-            all_boards.push(name.to_string());
+        ClientBuilder::new().build()
+    }
+
+    /// Creates a `ClientBuilder` to configure a `Client`.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Get the list of boards. The list is fetched from the API the first
+    /// time it is needed and then cached. Use `refresh_boards` to update it.
+    pub fn boards(&mut self) -> ::Result<&[BoardInfo]> {
+        if self.boards.is_none() {
+            try!(self.refresh_boards());
         }
+        Ok(&self.boards.as_ref().unwrap()[..])
+    }
 
-        Ok(Client{
-            reqwest_client: client,
-            all_boards: all_boards,
-            sfw_boards: sfw_boards,
-            nsfw_boards: nsfw_boards,
-            last_request: last_request,
-        })
+    /// Fetches the list of boards from the API, replacing the cached list.
+    pub fn refresh_boards(&mut self) -> ::Result<()> {
+        let url = self.api_url("/boards.json");
+        let boards = try!(fetch_boards(&self.sender(), &url));
+        self.set_boards(boards);
+        Ok(())
+    }
+
+    /// Replaces the cached list of boards and its index.
+    fn set_boards(&mut self, boards: Vec<BoardInfo>) {
+        self.board_index = boards.iter()
+            .enumerate()
+            .map(|(i, b)| (b.board.clone(), i))
+            .collect();
+        self.boards = Some(boards);
+    }
+
+    /// Iterates over the worksafe boards of the cached list of boards. Empty
+    /// if the list hasn't been fetched yet, see `boards`.
+    pub fn worksafe_boards(&self) -> impl Iterator<Item = &BoardInfo> {
+        self.cached_boards().iter().filter(|b| b.ws_board == 1)
+    }
+
+    /// Iterates over the boards of the cached list of boards that aren't
+    /// worksafe. Empty if the list hasn't been fetched yet, see `boards`.
+    pub fn nsfw_boards(&self) -> impl Iterator<Item = &BoardInfo> {
+        self.cached_boards().iter().filter(|b| b.ws_board != 1)
+    }
+
+    /// Looks up whether a board is worksafe in the cached list of boards.
+    /// Returns `None` if the board doesn't exist or the list hasn't been
+    /// fetched yet.
+    pub fn is_worksafe(&self, name: &str) -> Option<bool> {
+        self.board_index.get(name)
+            .map(|&i| self.cached_boards()[i].ws_board == 1)
+    }
+
+    /// Get the cached list of boards without fetching it.
+    fn cached_boards(&self) -> &[BoardInfo] {
+        self.boards.as_ref().map_or(&[][..], |b| &b[..])
+    }
+
+    /// Sets the minimum interval between two requests. Defaults to 1 second,
+    /// which is what the 4chan API rules require. Only lower this if you have
+    /// been given permission to do so.
+    pub fn set_min_interval(&mut self, interval: Duration) {
+        self.limiter.lock().unwrap().min_interval = interval;
+    }
+
+    /// Sets the base url of the JSON API. Defaults to `https://a.4cdn.org`.
+    /// Useful to point the client at a mock server when testing.
+    pub fn set_api_base(&mut self, url: &str) {
+        self.api_base = url.trim_end_matches('/').to_string();
+    }
+
+    /// Sets the base url of the media files. Defaults to
+    /// `https://i.4cdn.org`.
+    pub fn set_media_base(&mut self, url: &str) {
+        self.media_base = url.trim_end_matches('/').to_string();
+    }
+
+    /// Get the url of a JSON API path such as `/g/catalog.json`.
+    pub fn api_url(&self, path: &str) -> String {
+        format!("{}{}", self.api_base, path)
+    }
+
+    /// Get the url of a media path such as `/g/1492218205123.jpg`.
+    pub fn media_url(&self, path: &str) -> String {
+        format!("{}{}", self.media_base, path)
+    }
+
+    /// Get the urls of a media path on every media host, in the order they
+    /// should be tried. The first one is `media_url`.
+    pub fn media_urls(&self, path: &str) -> Vec<String> {
+        ::std::iter::once(&self.media_base)
+            .chain(&self.media_fallbacks)
+            .map(|base| format!("{}{}", base, path))
+            .collect()
+    }
+
+    /// Makes a GET request to the url, with the extra headers if provided.
+    ///
+    /// Sleeps for the remainder of the minimum interval if the last request
+    /// was made too recently. A client shared behind a `Mutex` stays locked
+    /// for the whole call if it is made on the locked client. `Board` and
+    /// `Thread` don't do that: they only lock the client to read what the
+    /// request needs, and wait and send without the lock, so that they don't
+    /// block each other while waiting for the rate limit.
+    ///
+    /// Server errors and connection errors are retried with an exponential
+    /// backoff. Client errors such as 404 are never retried. Once out of
+    /// retries, the last response or error is returned.
+    ///
+    /// The headers are sent on top of the default ones of the client, and
+    /// replace any default header of the same name, such as User-Agent.
+    pub fn get(&mut self, url: &str, headers: Option<HeaderMap>)
+        -> ::Result<::reqwest::Response> {
+        self.sender().get(url, headers)
+    }
+
+    /// Makes a GET request to the url of a media file like `get`. Media
+    /// requests share the rate limit of API requests, unless the client was
+    /// built with `ClientBuilder::separate_media_limiter`.
+    pub fn get_media(&mut self, url: &str) -> ::Result<::reqwest::Response> {
+        self.sender().get_media(url)
+    }
+
+    /// Makes a GET request like `get` and parses the body as untyped JSON.
+    /// This is the fallback for when the typed methods fail to deserialize a
+    /// response, such as after a change of the API schema. Returns `None` on
+    /// 304 Not Modified, and `Error::UnexpectedStatus` on any other status
+    /// that isn't a success.
+    pub fn get_json(&mut self, url: &str, headers: Option<HeaderMap>)
+        -> ::Result<Option<::serde_json::Value>> {
+        let mut res = try!(self.get(url, headers));
+        match res.status() {
+            StatusCode::NOT_MODIFIED => Ok(None),
+            status if status.is_success() => {
+                let buf = try!(::read_text(&mut res));
+                Ok(Some(try!(::serde_json::from_str(&buf))))
+            },
+            status => Err(::Error::UnexpectedStatus(status))
+        }
+    }
+
+    /// Makes a HEAD request to the url, throttled and retried like `get`.
+    pub fn head(&mut self, url: &str) -> ::Result<::reqwest::Response> {
+        self.sender().head(url)
+    }
+
+    /// Get a `Sender` to make requests like the client does. Only handles
+    /// are cloned, so a shared client need only be locked for this call.
+    pub(crate) fn sender(&self) -> Sender {
+        Sender {
+            reqwest_client: self.reqwest_client.clone(),
+            #[cfg(feature = "async")]
+            async_client: self.async_client.clone(),
+            limiter: self.limiter.clone(),
+            retries: self.retries,
+            backoff_base: self.backoff_base,
+            validator: self.validator
+        }
+    }
+
+    /// Sets which validators conditional requests send. Defaults to
+    /// `Validator::LastModified`.
+    pub fn set_validator(&mut self, validator: Validator) {
+        self.validator = validator;
+    }
+
+    pub fn validator(&self) -> Validator {
+        self.validator
     }
 
-    /// Makes a GET request to the url. Adds an "If-Modified-Since" header if
-    /// provided.
-    pub fn get(&mut self, url: &str, headers: Option<::IfModifiedSince>)
+    /// Makes a conditional GET request like `get`. Sends If-Modified-Since
+    /// and If-None-Match from the date and the ETag that are provided,
+    /// depending on the validators the client is configured to use.
+    pub fn get_conditional(&mut self,
+                           url: &str,
+                           since: Option<::IfModifiedSince>,
+                           etag: Option<&str>)
         -> ::Result<::reqwest::Response> {
-        // Throttle so that we make no more than 1 request per second.
-        let diff = UTC::now().signed_duration_since(self.last_request);
-        if diff < Duration::seconds(1) {
-            sleep(try!(diff.to_std()));
+        self.sender().get_conditional(url, since, etag)
+    }
+
+    /// Makes a GET request like `get`, with only an If-Modified-Since header
+    /// if the date is provided.
+    pub fn get_if_modified_since(&mut self,
+                                 url: &str,
+                                 since: Option<::IfModifiedSince>)
+        -> ::Result<::reqwest::Response> {
+        self.sender().get_if_modified_since(url, since)
+    }
+
+    /// Get the `BoardInfo` of a board, fetching the list of boards if it
+    /// has not been fetched yet. Returns `None` if the board doesn't exist.
+    pub fn board_info(&mut self, name: &str) -> ::Result<Option<&BoardInfo>> {
+        if self.boards.is_none() {
+            try!(self.refresh_boards());
         }
+        let boards = self.boards.as_ref().unwrap();
+        Ok(self.board_index.get(name).map(|&i| &boards[i]))
+    }
+
+    pub fn is_sfw(&mut self, name: &str) -> ::Result<bool> {
+        Ok(try!(self.board_info(name)).map_or(false, |b| b.ws_board == 1))
+    }
+
+    pub fn is_nsfw(&mut self, name: &str) -> ::Result<bool> {
+        Ok(try!(self.board_info(name)).map_or(false, |b| b.ws_board != 1))
+    }
 
-        let mut req_headers = Headers::new();
-        req_headers.set(UserAgent("clover-rs".to_string()));
-        if headers.is_some() {
-            for header in headers {
-                req_headers.set(header);
+    pub fn is_valid_board(&mut self, name: &str) -> ::Result<bool> {
+        Ok(try!(self.board_info(name)).is_some())
+    }
+}
+
+/// A `Sender` makes requests on behalf of a `Client`, sharing its `reqwest`
+/// client and its rate limit. It is what lets a shared client be unlocked
+/// while a request waits for its turn and is sent.
+#[derive(Clone, Debug)]
+pub(crate) struct Sender {
+    reqwest_client: ::reqwest::Client,
+    #[cfg(feature = "async")]
+    async_client: ::reqwest::async::Client,
+    limiter: Arc<Mutex<Limiter>>,
+    retries: u32,
+    backoff_base: Duration,
+    validator: Validator
+}
+
+#[allow(deprecated)]
+impl Sender {
+    /// Makes a GET request like `Client::get`.
+    pub fn get(&self, url: &str, headers: Option<HeaderMap>)
+        -> ::Result<::reqwest::Response> {
+        self.request(Method::GET, url, headers, false)
+    }
+
+    /// Makes a GET request like `Client::get_media`.
+    pub fn get_media(&self, url: &str) -> ::Result<::reqwest::Response> {
+        self.request(Method::GET, url, None, true)
+    }
+
+    /// Makes a HEAD request like `Client::head`.
+    pub fn head(&self, url: &str) -> ::Result<::reqwest::Response> {
+        self.request(Method::HEAD, url, None, false)
+    }
+
+    /// Makes a conditional GET request like `Client::get_conditional`.
+    pub fn get_conditional(&self,
+                           url: &str,
+                           since: Option<::IfModifiedSince>,
+                           etag: Option<&str>)
+        -> ::Result<::reqwest::Response> {
+        let headers = try!(self.conditional_headers(since, etag));
+        self.get(url, Some(headers))
+    }
+
+    /// Builds the headers of a conditional request, sending the validators
+    /// the client is configured to.
+    fn conditional_headers(&self,
+                           since: Option<::IfModifiedSince>,
+                           etag: Option<&str>) -> ::Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        if let Some(since) = since {
+            if self.validator != Validator::ETag {
+                headers.insert(IF_MODIFIED_SINCE,
+                               try!(HeaderValue::from_str(&since.0)));
             }
         }
+        if let Some(etag) = etag {
+            if self.validator != Validator::LastModified {
+                headers.insert(IF_NONE_MATCH,
+                               try!(HeaderValue::from_str(etag)));
+            }
+        }
+        Ok(headers)
+    }
 
-        debug!("[{:?}] Making request to url: {} with headers: {:?}",
-               UTC::now(), url, req_headers);
+    /// Makes a GET request like `Client::get_if_modified_since`.
+    pub fn get_if_modified_since(&self,
+                                 url: &str,
+                                 since: Option<::IfModifiedSince>)
+        -> ::Result<::reqwest::Response> {
+        let headers = match since {
+            Some(since) => {
+                let mut headers = HeaderMap::new();
+                headers.insert(IF_MODIFIED_SINCE,
+                               try!(HeaderValue::from_str(&since.0)));
+                Some(headers)
+            },
+            None => None
+        };
+        self.get(url, headers)
+    }
+
+    /// Makes a throttled request, retrying it on transient errors. Only the
+    /// limiter is locked, and only to reserve a slot, not while waiting.
+    fn request(&self,
+               method: Method,
+               url: &str,
+               headers: Option<HeaderMap>,
+               media: bool) -> ::Result<::reqwest::Response> {
+        let req_headers = headers.unwrap_or_else(HeaderMap::new);
+
+        let mut attempt = 0;
+        let mut backoff = None;
+        loop {
+            let at = self.limiter.lock().unwrap().reserve(backoff, media);
+            let now = Instant::now();
+            if at > now {
+                sleep(at - now);
+            }
 
-        let res = try!(self.reqwest_client.get(url)
-                           .headers(req_headers)
-                           .send());
+            debug!("[{:?}] Making {} request to url: {} with headers: {:?}",
+                   UTC::now(), method, url, req_headers);
 
-        self.last_request = UTC::now();
+            let res = self.reqwest_client.request(method.clone(), url)
+                .headers(req_headers.clone())
+                .send();
 
-        Ok(res)
+            if let Ok(ref res) = res {
+                if res.status() == StatusCode::TOO_MANY_REQUESTS {
+                    self.limiter.lock().unwrap().cool_down(url);
+                }
+            }
+
+            let outcome = res.as_ref().map(|res| res.status());
+            backoff = self.retry_backoff(url, attempt, outcome);
+            if backoff.is_none() {
+                return Ok(try!(res))
+            }
+            attempt += 1;
+        }
     }
 
-    pub fn is_sfw(&self, name: &str) -> bool {
-        self.sfw_boards.contains(&name.to_string())
+    /// Decides whether to retry a request after a failed attempt, counting
+    /// from 0. Server and connection errors are transient, and retried with
+    /// an exponential backoff, which is returned. Returns `None` if the
+    /// outcome is final.
+    fn retry_backoff(&self,
+                     url: &str,
+                     attempt: u32,
+                     outcome: Result<StatusCode, &::reqwest::Error>)
+        -> Option<Duration> {
+        let transient = match outcome {
+            Ok(status) => status.is_server_error(),
+            Err(e) => e.is_http() || e.is_timeout()
+        };
+        if !transient || attempt >= self.retries {
+            return None
+        }
+
+        let backoff = self.backoff_base * (1 << cmp::min(attempt, 16));
+        match outcome {
+            Ok(status) => warn!("Got {} from {}, retry {} of {} in {:?}",
+                                status, url, attempt + 1, self.retries,
+                                backoff),
+            Err(e) => warn!("Request to {} failed: {}, retry {} of {} in {:?}",
+                            url, e, attempt + 1, self.retries, backoff)
+        }
+        Some(backoff)
     }
+}
 
-    pub fn is_nsfw(&self, name: &str) -> bool {
-        self.nsfw_boards.contains(&name.to_string())
+#[cfg(feature = "async")]
+#[allow(deprecated)]
+impl Sender {
+    /// Makes a conditional GET request like `get_conditional`, but without
+    /// blocking, see `get_async`.
+    pub fn get_conditional_async(&self,
+                                 url: &str,
+                                 since: Option<::IfModifiedSince>,
+                                 etag: Option<&str>)
+        -> ::AsyncFuture<AsyncResponse> {
+        match self.conditional_headers(since, etag) {
+            Ok(headers) => self.get_async(url, headers),
+            Err(e) => Box::new(future::err(e))
+        }
     }
 
-    pub fn is_valid_board(&self, name: &str) -> bool {
-        self.all_boards.contains(&name.to_string())
+    /// Makes a GET request like `get`, but without blocking. The slot of the
+    /// request is reserved from the same limiter, and waited for with a
+    /// timer, so the rate limit is shared with blocking requests. The body
+    /// is read before the future resolves.
+    ///
+    /// The future must run on a tokio runtime.
+    pub fn get_async(&self, url: &str, headers: HeaderMap)
+        -> ::AsyncFuture<AsyncResponse> {
+        let sender = self.clone();
+        let url = url.to_string();
+        let send = future::loop_fn((0, None), move |(attempt, backoff)| {
+            let at = sender.limiter.lock().unwrap().reserve(backoff, false);
+            let (sender, url, headers) =
+                (sender.clone(), url.clone(), headers.clone());
+            Delay::new(at).map_err(::Error::from).and_then(move |_| {
+                debug!("[{:?}] Making async GET request to url: {} with \
+                        headers: {:?}", UTC::now(), url, headers);
+                sender.async_client.get(&url).headers(headers).send()
+                    .then(move |res| {
+                        if let Ok(ref res) = res {
+                            if res.status() == StatusCode::TOO_MANY_REQUESTS {
+                                sender.limiter.lock().unwrap().cool_down(&url);
+                            }
+                        }
+
+                        let outcome = res.as_ref().map(|res| res.status());
+                        match sender.retry_backoff(&url, attempt, outcome) {
+                            Some(backoff) => Ok(future::Loop::Continue(
+                                (attempt + 1, Some(backoff)))),
+                            None => Ok(future::Loop::Break(try!(res)))
+                        }
+                    })
+            })
+        });
+
+        Box::new(send.and_then(|res| {
+            let status = res.status();
+            let headers = res.headers().clone();
+            let url = res.url().to_string();
+            res.into_body().concat2().map_err(::Error::from).map(move |body| {
+                AsyncResponse {
+                    status,
+                    headers,
+                    text: ::text(body.to_vec(), &url)
+                }
+            })
+        }))
     }
 }
 
+/// A response of an async request, with its body read as text.
+#[cfg(feature = "async")]
+pub(crate) struct AsyncResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub text: String
+}
+
+/// A `Limiter` hands out the times at which requests may be sent. Only the
+/// bookkeeping happens under its lock; requests wait for their time without
+/// holding it.
+#[derive(Debug)]
+struct Limiter {
+    min_interval: Duration,
+    // Media requests are throttled on their own if this is set.
+    media_min_interval: Option<Duration>,
+    // The earliest times the next API and media requests may be sent.
+    next_request: Option<Instant>,
+    next_media_request: Option<Instant>,
+    rate_limit_cooldown: Duration,
+    // No request is made before this after a 429 Too Many Requests.
+    cooldown_until: Option<Instant>
+}
+
+impl Limiter {
+    /// Reserves the next slot for a request and returns when it starts. A
+    /// backoff counts from now, and the later of it and the slot is used
+    /// rather than their sum. A rate limit cooldown comes on top.
+    ///
+    /// Media requests get slots of their own if they have their own interval.
+    /// The cooldown is shared regardless.
+    fn reserve(&mut self, backoff: Option<Duration>, media: bool) -> Instant {
+        let now = Instant::now();
+        let (next, interval) = match self.media_min_interval {
+            Some(interval) if media => (&mut self.next_media_request, interval),
+            _ => (&mut self.next_request, self.min_interval)
+        };
+        let mut at = next.map_or(now, |next| cmp::max(next, now));
+        if let Some(backoff) = backoff {
+            at = cmp::max(at, now + backoff);
+        }
+        if let Some(cooldown_until) = self.cooldown_until {
+            at = cmp::max(at, cooldown_until);
+        }
+        *next = Some(at + interval);
+        at
+    }
+
+    /// Holds back every request for the cooldown after a 429 from the url.
+    fn cool_down(&mut self, url: &str) {
+        warn!("Rate limited on {}, cooling down for {:?}", url,
+              self.rate_limit_cooldown);
+        self.cooldown_until = Some(Instant::now() + self.rate_limit_cooldown);
+    }
+}
+
+/// Fetches the list of boards of a shared client, unless it has it already.
+/// The client is not locked during the request.
+#[allow(deprecated)]
+pub(crate) fn ensure_boards(client: &Mutex<Client>) -> ::Result<()> {
+    let (sender, url) = {
+        let client = client.lock().unwrap();
+        if client.boards.is_some() {
+            return Ok(())
+        }
+        (client.sender(), client.api_url("/boards.json"))
+    };
+    let boards = try!(fetch_boards(&sender, &url));
+    client.lock().unwrap().set_boards(boards);
+    Ok(())
+}
+
+/// Requests and parses the list of boards.
+#[allow(deprecated)]
+fn fetch_boards(sender: &Sender, url: &str) -> ::Result<Vec<BoardInfo>> {
+    let mut res = try!(sender.get(url, None));
+    if !res.status().is_success() {
+        return Err(::Error::UnexpectedStatus(res.status()))
+    }
+
+    let mut buf = String::new();
+    try!(res.read_to_string(&mut buf));
+    let deserializer: BoardsDeserializer = try!(
+        ::serde_json::from_str(&buf));
+    Ok(deserializer.boards)
+}
+
+/// Applies the settings of a `ClientBuilder` to a `reqwest` client builder.
+/// The blocking and the async builders have the same methods, but no trait
+/// in common.
+macro_rules! reqwest_builder {
+    ($builder:expr, $config:expr, $headers:expr) => {{
+        let mut builder = $builder.default_headers($headers);
+        if let Some(timeout) = $config.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(ref proxy) = $config.proxy {
+            let proxy = try!(::reqwest::Proxy::all(proxy.as_str()));
+            builder = builder.proxy(proxy);
+        }
+        if let Some(max_idle) = $config.pool_max_idle {
+            builder = builder.max_idle_per_host(max_idle);
+        }
+        builder
+    }}
+}
+
+/// A `ClientBuilder` configures and builds a `Client`.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// let client = clover::Client::builder()
+///     .user_agent("my-bot/1.0")
+///     .timeout(Duration::from_secs(10))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    user_agent: String,
+    timeout: Option<Duration>,
+    min_interval: Duration,
+    retries: u32,
+    backoff_base: Duration,
+    proxy: Option<String>,
+    pool_max_idle: Option<usize>,
+    media_min_interval: Option<Duration>,
+    rate_limit_cooldown: Duration,
+    validator: Validator,
+    api_base: String,
+    media_base: String,
+    media_fallbacks: Vec<String>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+}
+
+#[allow(deprecated)]
+impl ClientBuilder {
+    /// Creates a new `ClientBuilder` with the default configuration.
+    pub fn new() -> ClientBuilder {
+        ClientBuilder {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            timeout: None,
+            min_interval: Duration::from_millis(DEFAULT_MIN_INTERVAL_MS),
+            retries: DEFAULT_RETRIES,
+            backoff_base: Duration::from_millis(DEFAULT_BACKOFF_BASE_MS),
+            proxy: None,
+            pool_max_idle: None,
+            media_min_interval: None,
+            rate_limit_cooldown: Duration::from_secs(
+                DEFAULT_RATE_LIMIT_COOLDOWN_SECS),
+            validator: Validator::LastModified,
+            api_base: API_BASE.to_string(),
+            media_base: MEDIA_BASE.to_string(),
+            media_fallbacks: Vec::new(),
+        }
+    }
+
+    /// Sets the user agent sent with every request. Defaults to
+    /// `clover/{version}`.
+    pub fn user_agent(&mut self, user_agent: &str) -> &mut ClientBuilder {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Sets the timeout of every request, media downloads included. Uses the
+    /// `reqwest` default if not set. A request that times out fails with
+    /// `Error::Timeout`.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut ClientBuilder {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the minimum interval between two requests. Defaults to 1 second,
+    /// which is what the 4chan API rules require. Only lower this if you have
+    /// been given permission to do so.
+    pub fn min_interval(&mut self, interval: Duration) -> &mut ClientBuilder {
+        self.min_interval = interval;
+        self
+    }
+
+    /// Sets the number of times a request is retried on a server error or a
+    /// connection error. Defaults to 2.
+    pub fn retries(&mut self, retries: u32) -> &mut ClientBuilder {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets the backoff before the first retry of a request. It doubles on
+    /// every following retry. Defaults to 1 second.
+    pub fn backoff_base(&mut self, backoff: Duration) -> &mut ClientBuilder {
+        self.backoff_base = backoff;
+        self
+    }
+
+    /// Sends every request through the proxy at the url.
+    pub fn proxy(&mut self, url: &str) -> &mut ClientBuilder {
+        self.proxy = Some(url.to_string());
+        self
+    }
+
+    /// Sets the maximum number of idle keep-alive connections kept open per
+    /// host. Uses the `reqwest` default if not set.
+    pub fn pool_max_idle(&mut self, max_idle: usize) -> &mut ClientBuilder {
+        self.pool_max_idle = Some(max_idle);
+        self
+    }
+
+    /// Throttles media downloads with their own minimum interval instead of
+    /// sharing the rate limit of API requests, which is the default. Media
+    /// is served from another host than the API, so a bulk download then
+    /// doesn't delay catalog polling by its requests. A 429 cooldown still
+    /// holds back both.
+    pub fn separate_media_limiter(&mut self, interval: Duration)
+        -> &mut ClientBuilder {
+        self.media_min_interval = Some(interval);
+        self
+    }
+
+    /// Sets how long every request is held back after a response of 429 Too
+    /// Many Requests, so that all the boards sharing the client back off.
+    /// Defaults to 30 seconds.
+    pub fn rate_limit_cooldown(&mut self, cooldown: Duration)
+        -> &mut ClientBuilder {
+        self.rate_limit_cooldown = cooldown;
+        self
+    }
+
+    /// Sets which validators conditional requests send. Defaults to
+    /// `Validator::LastModified`.
+    pub fn validator(&mut self, validator: Validator) -> &mut ClientBuilder {
+        self.validator = validator;
+        self
+    }
+
+    /// Sets the base url of the JSON API. Defaults to `https://a.4cdn.org`.
+    pub fn api_base(&mut self, url: &str) -> &mut ClientBuilder {
+        self.api_base = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Sets the base url of the media files. Defaults to
+    /// `https://i.4cdn.org`.
+    pub fn media_base(&mut self, url: &str) -> &mut ClientBuilder {
+        self.media_base = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Sets the base urls of the media hosts, such as `https://i.4cdn.org`
+    /// and `https://is2.4chan.org`. The first one replaces `media_base`, and
+    /// a download that fails with a server or connection error on a host is
+    /// retried on the next one. Every attempt is rate limited. Ignored if
+    /// empty.
+    pub fn media_hosts(&mut self, hosts: Vec<String>) -> &mut ClientBuilder {
+        let mut hosts = hosts.iter()
+            .map(|url| url.trim_end_matches('/').to_string());
+        if let Some(primary) = hosts.next() {
+            self.media_base = primary;
+            self.media_fallbacks = hosts.collect();
+        }
+        self
+    }
+
+    /// Builds the `Client`.
+    pub fn build(&self) -> ::Result<Client> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT,
+                       try!(HeaderValue::from_str(&self.user_agent)));
+
+        let builder = reqwest_builder!(::reqwest::Client::builder(), self,
+                                       headers.clone());
+        Ok(Client {
+            reqwest_client: try!(builder.build()),
+            #[cfg(feature = "async")]
+            async_client: try!(reqwest_builder!(
+                ::reqwest::async::Client::builder(), self, headers).build()),
+            boards: None,
+            board_index: HashMap::new(),
+            limiter: Arc::new(Mutex::new(Limiter {
+                min_interval: self.min_interval,
+                media_min_interval: self.media_min_interval,
+                next_request: None,
+                next_media_request: None,
+                rate_limit_cooldown: self.rate_limit_cooldown,
+                cooldown_until: None
+            })),
+            retries: self.retries,
+            backoff_base: self.backoff_base,
+            validator: self.validator,
+            api_base: self.api_base.clone(),
+            media_base: self.media_base.clone(),
+            media_fallbacks: self.media_fallbacks.clone(),
+        })
+    }
+}
+
+/// The validators a conditional request sends to learn whether a resource
+/// changed since it was last fetched. Either way a 304 means it didn't.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Validator {
+    // If-Modified-Since, from the Last-Modified of the last response.
+    LastModified,
+    // If-None-Match, from the ETag of the last response.
+    ETag,
+    // Both of them.
+    Both
+}
+
+/// A `BoardInfo` is an entry of the boards.json endpoint.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BoardInfo {
+    pub board: String,
+    pub title: String,
+    // 1 if the board is worksafe, 0 otherwise.
+    pub ws_board: u8,
+    #[serde(default="default_per_page")]
+    pub per_page: u32,
+    #[serde(default="default_pages")]
+    pub pages: u32
+}
+
+impl BoardInfo {
+    /// Get the number of pages of the board, so the most a catalog can
+    /// have. Defaults to 10 if boards.json doesn't say.
+    pub fn pages(&self) -> u32 {
+        self.pages
+    }
+
+    /// Get the number of threads on a page of the board. Defaults to 15 if
+    /// boards.json doesn't say.
+    pub fn per_page(&self) -> u32 {
+        self.per_page
+    }
+
+    /// Get the most threads the board can have alive at once.
+    pub fn max_threads(&self) -> u32 {
+        self.pages * self.per_page
+    }
+}
+
+fn default_pages() -> u32 {
+    DEFAULT_PAGES
+}
+
+fn default_per_page() -> u32 {
+    DEFAULT_PER_PAGE
+}
+
+#[derive(Deserialize)]
+struct BoardsDeserializer {
+    boards: Vec<BoardInfo>
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn get_board() {
         let client = Arc::new(Mutex::new(::Client::new().unwrap()));
         let g = ::Board::new(client, "g").unwrap();
         let _ = g.catalog().unwrap();
-        assert!(g.thread_cache.lock().unwrap().threads.len() > 0);
+        assert!(!g.thread_cache.read().unwrap().threads.is_empty());
         let sticky_candidates = g.find_cached("installgentoo")
             .expect("Found no matches for installgentoo");
-        assert!(sticky_candidates.len() > 0);
+        assert!(!sticky_candidates.is_empty());
+    }
+
+    #[test]
+    fn worksafe_boards() {
+        let mut client = ::Client::new().unwrap();
+        assert_eq!(0, client.worksafe_boards().count());
+        assert_eq!(None, client.is_worksafe("g"));
+
+        let deserializer: super::BoardsDeserializer = ::serde_json::from_str(
+            r#"{"boards": [
+                {"board": "a", "title": "Anime & Manga", "ws_board": 1,
+                 "per_page": 15, "pages": 10},
+                {"board": "b", "title": "Random", "ws_board": 0,
+                 "per_page": 15, "pages": 10},
+                {"board": "g", "title": "Technology", "ws_board": 1,
+                 "per_page": 15, "pages": 10}
+            ]}"#).unwrap();
+        client.set_boards(deserializer.boards);
+
+        let worksafe: Vec<&str> = client.worksafe_boards()
+            .map(|b| b.board.as_str())
+            .collect();
+        assert_eq!(vec!["a", "g"], worksafe);
+        let nsfw: Vec<&str> = client.nsfw_boards()
+            .map(|b| b.board.as_str())
+            .collect();
+        assert_eq!(vec!["b"], nsfw);
+        assert_eq!(Some(true), client.is_worksafe("g"));
+        assert_eq!(Some(false), client.is_worksafe("b"));
+        assert_eq!(None, client.is_worksafe("z"));
     }
-}
 
+    #[test]
+    fn board_info_pages() {
+        let info: ::BoardInfo = ::serde_json::from_str(
+            r#"{"board": "g", "title": "Technology", "ws_board": 1,
+                "per_page": 20, "pages": 5}"#).unwrap();
+        assert_eq!(5, info.pages());
+        assert_eq!(20, info.per_page());
+        assert_eq!(100, info.max_threads());
+
+        let info: ::BoardInfo = ::serde_json::from_str(
+            r#"{"board": "g", "title": "Technology", "ws_board": 1}"#)
+            .unwrap();
+        assert_eq!(10, info.pages());
+        assert_eq!(15, info.per_page());
+    }
+
+    #[test]
+    fn get_json() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("200 OK", r#"{"boards": 1}"#),
+            ::mock::response("304 Not Modified", "")]);
+
+        let mut client = ::Client::builder()
+            .min_interval(Duration::from_millis(0))
+            .build()
+            .unwrap();
+        let url = format!("{}/boards.json", base);
+        let json = client.get_json(&url, None).unwrap().unwrap();
+        assert_eq!(Some(1), json["boards"].as_u64());
+        assert!(client.get_json(&url, None).unwrap().is_none());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn get_cools_down_after_429() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("429 Too Many Requests", ""),
+            ::mock::response("200 OK", "")]);
+
+        let mut client = ::Client::builder()
+            .min_interval(Duration::from_millis(0))
+            .rate_limit_cooldown(Duration::from_secs(2))
+            .build()
+            .unwrap();
+        let url = format!("{}/boards.json", base);
+        let res = client.get(&url, None).unwrap();
+        assert_eq!(::reqwest::StatusCode::TOO_MANY_REQUESTS, res.status());
+        let start = Instant::now();
+        assert!(client.get(&url, None).unwrap().status().is_success());
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn get_media_separate_limiter() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("200 OK", ""),
+            ::mock::response("200 OK", "")]);
+
+        let mut client = ::Client::builder()
+            .min_interval(Duration::from_secs(5))
+            .separate_media_limiter(Duration::from_millis(0))
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        client.get(&format!("{}/boards.json", base), None).unwrap();
+        client.get_media(&format!("{}/g/1.jpg", base)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn get_times_out() {
+        // Accepts the connection but never responds.
+        let (base, server) = ::mock::serve_with(1, |_| {
+            thread::sleep(Duration::from_secs(2));
+            Vec::new()
+        });
+
+        let mut client = ::Client::builder()
+            .timeout(Duration::from_millis(500))
+            .retries(0)
+            .build()
+            .unwrap();
+        match client.get(&format!("{}/boards.json", base), None) {
+            Err(::Error::Timeout) => (),
+            res => panic!("Expected Timeout, got {:?}", res)
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn get_is_throttled() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("200 OK", ""),
+            ::mock::response("200 OK", ""),
+            ::mock::response("200 OK", "")]);
+
+        let mut client = ::Client::new().unwrap();
+        let start = Instant::now();
+        let url = format!("{}/boards.json", base);
+        for _ in 0..3 {
+            let _ = client.get(&url, None).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        server.join().unwrap();
+    }
+}
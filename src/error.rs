@@ -4,66 +4,148 @@ use std::fmt;
 /// Any error from this crate.
 #[derive(Debug)]
 pub enum Error {
+    // An error from the `base64` crate. Failed to decode a digest.
+    Base64(::base64::DecodeError),
+    // A header value contained invalid characters.
+    Header(::reqwest::header::InvalidHeaderValue),
     // An error from the `reqwest` crate.
     Http(::reqwest::Error),
     // An error from the `serde` crate for deserializing json.
     Json(::serde_json::Error),
     // A `std::io` error.
     Read(::std::io::Error),
-    // An error from the `regex` crate. Failed to build a regex.
-    Regex(::regex::Error),
+    // A search query is not a valid regex. Carries the error message.
+    InvalidQuery(String),
     // An error from the `time` crate that `chrono` uses.
     // Signifies a bad conversion between `chrono::Duration` and
     // `std::time::Duration`.
     Time(::time::OutOfRangeError),
     // Tried to create a board that doesn't exist.
     InvalidBoardName,
-    // Unexpected HTTP response received.
-    UnexpectedResponse
+    // The board does not have an archive.
+    ArchiveNotAvailable,
+    // The server responded with an unexpected status code.
+    UnexpectedStatus(::reqwest::StatusCode),
+    // The post has no file attached.
+    NoAttachment,
+    // The downloaded file does not match the post's MD5 digest.
+    ChecksumMismatch,
+    // The thread does not exist, or has been pruned.
+    ThreadNotFound(u64),
+    // A request timed out.
+    Timeout,
+    // The file attached to the post was deleted.
+    FileDeleted,
+    // An error from the `tokio-timer` crate. An async request waited for
+    // the rate limit outside of a tokio runtime.
+    #[cfg(feature = "async")]
+    Timer(::tokio_timer::Error)
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Error::Base64(ref e) => fmt::Display::fmt(e, f),
+            Error::Header(ref e) => fmt::Display::fmt(e, f),
             Error::Http(ref e) => fmt::Display::fmt(e, f),
             Error::Json(ref e) => fmt::Display::fmt(e, f),
             Error::Read(ref e) => fmt::Display::fmt(e, f),
-            Error::Regex(ref e) => fmt::Display::fmt(e, f),
+            Error::InvalidQuery(ref e) => write!(f, "Invalid query: {}", e),
             Error::Time(ref e) => fmt::Display::fmt(e, f),
             Error::InvalidBoardName => f.pad("Invalid board name"),
-            Error::UnexpectedResponse => f.pad("Unexpected HTTP response received")
+            Error::ArchiveNotAvailable => f.pad("Board has no archive"),
+            Error::UnexpectedStatus(ref status) => {
+                write!(f, "Unexpected HTTP status {}", status.as_u16())
+            },
+            Error::NoAttachment => f.pad("Post has no attachment"),
+            Error::ChecksumMismatch => f.pad("Checksum mismatch"),
+            Error::ThreadNotFound(no) => write!(f, "Thread {} not found", no),
+            Error::Timeout => f.pad("Request timed out"),
+            Error::FileDeleted => f.pad("File was deleted"),
+            #[cfg(feature = "async")]
+            Error::Timer(ref e) => fmt::Display::fmt(e, f)
+        }
+    }
+}
+
+impl Error {
+    /// Returns `true` if the server responded with 429 Too Many Requests,
+    /// in which case requests should back off harder.
+    pub fn is_rate_limited(&self) -> bool {
+        match *self {
+            Error::UnexpectedStatus(status) => {
+                status == ::reqwest::StatusCode::TOO_MANY_REQUESTS
+            },
+            _ => false
         }
     }
 }
 
 impl StdError for Error {
+    // Forwards to the deprecated `description` of the causes.
+    #[allow(deprecated)]
     fn description(&self) -> &str {
         match *self {
+            Error::Base64(ref e) => e.description(),
+            Error::Header(ref e) => e.description(),
             Error::Http(ref e) => e.description(),
             Error::Json(ref e) => e.description(),
             Error::Read(ref e) => e.description(),
-            Error::Regex(ref e) => e.description(),
+            Error::InvalidQuery(_) => "Invalid query",
             Error::Time(ref e) => e.description(),
             Error::InvalidBoardName => "Invalid board name",
-            Error::UnexpectedResponse => "Unexpected HTTP response received"
+            Error::ArchiveNotAvailable => "Board has no archive",
+            Error::UnexpectedStatus(_) => "Unexpected HTTP status",
+            Error::NoAttachment => "Post has no attachment",
+            Error::ChecksumMismatch => "Checksum mismatch",
+            Error::ThreadNotFound(_) => "Thread not found",
+            Error::Timeout => "Request timed out",
+            Error::FileDeleted => "File was deleted",
+            #[cfg(feature = "async")]
+            Error::Timer(ref e) => e.description()
         }
     }
 
-    fn cause(&self) -> Option<&StdError> {
+    fn cause(&self) -> Option<&dyn StdError> {
         match *self {
+            Error::Base64(ref e) => Some(e),
+            Error::Header(ref e) => Some(e),
             Error::Http(ref e) => Some(e),
             Error::Json(ref e) => Some(e),
             Error::Read(ref e) => Some(e),
-            Error::Regex(ref e) => Some(e),
+            Error::InvalidQuery(_) => None,
             Error::Time(ref e) => Some(e),
             Error::InvalidBoardName => None,
-            Error::UnexpectedResponse => None
+            Error::ArchiveNotAvailable => None,
+            Error::UnexpectedStatus(_) => None,
+            Error::NoAttachment => None,
+            Error::ChecksumMismatch => None,
+            Error::ThreadNotFound(_) => None,
+            Error::Timeout => None,
+            Error::FileDeleted => None,
+            #[cfg(feature = "async")]
+            Error::Timer(ref e) => Some(e)
         }
     }
 }
 
+impl From<::base64::DecodeError> for Error {
+    fn from(err: ::base64::DecodeError) -> Error {
+        Error::Base64(err)
+    }
+}
+
+impl From<::reqwest::header::InvalidHeaderValue> for Error {
+    fn from(err: ::reqwest::header::InvalidHeaderValue) -> Error {
+        Error::Header(err)
+    }
+}
+
 impl From<::reqwest::Error> for Error {
     fn from(err: ::reqwest::Error) -> Error {
+        if err.is_timeout() {
+            return Error::Timeout
+        }
         Error::Http(err)
     }
 }
@@ -76,13 +158,17 @@ impl From<::serde_json::Error> for Error {
 
 impl From<::std::io::Error> for Error {
     fn from(err: ::std::io::Error) -> Error {
+        // Reading the body of a response that times out fails this way.
+        if err.kind() == ::std::io::ErrorKind::TimedOut {
+            return Error::Timeout
+        }
         Error::Read(err)
     }
 }
 
 impl From<::regex::Error> for Error {
     fn from(err: ::regex::Error) -> Error {
-        Error::Regex(err)
+        Error::InvalidQuery(err.to_string())
     }
 }
 
@@ -92,5 +178,26 @@ impl From<::time::OutOfRangeError> for Error {
     }
 }
 
+#[cfg(feature = "async")]
+impl From<::tokio_timer::Error> for Error {
+    fn from(err: ::tokio_timer::Error) -> Error {
+        Error::Timer(err)
+    }
+}
+
 /// A `Result` alias where the `Err` case is `clover::Error`
 pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use reqwest::StatusCode;
+
+    #[test]
+    fn unexpected_status_test() {
+        let err = ::Error::UnexpectedStatus(StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!("Unexpected HTTP status 429", err.to_string());
+        assert!(err.is_rate_limited());
+        assert!(!::Error::UnexpectedStatus(StatusCode::FORBIDDEN)
+                .is_rate_limited());
+    }
+}
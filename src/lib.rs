@@ -1,10 +1,17 @@
 #![deny(warnings)]
+// `try!` is deprecated, but kept for the 2015 edition. The items that use it
+// allow `deprecated` themselves.
 
+extern crate base64;
 extern crate chrono;
+extern crate csv;
+#[cfg(feature = "async")]
+extern crate futures;
 #[macro_use]
-extern crate hyper;
+extern crate lazy_static;
 #[macro_use]
 extern crate log;
+extern crate md5;
 extern crate regex;
 extern crate reqwest;
 extern crate serde;
@@ -12,19 +19,101 @@ extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
 extern crate time;
+#[cfg(all(test, feature = "async"))]
+extern crate tokio;
+#[cfg(feature = "async")]
+extern crate tokio_timer;
 
-pub use self::board::Board;
-pub use self::client::Client;
+use std::io::Read;
+
+pub use self::board::{Board, CacheOutcome, Catalog, CatalogDiff, MultiBoard,
+                      MultiSearch, Page, RefreshSummary, ThreadSummary,
+                      Topics};
+pub use self::client::{BoardInfo, Client, ClientBuilder, Validator};
 pub use self::error::{Error, Result};
-pub use self::post::{LastReply, Post};
-pub use self::thread::{Thread, ThreadCache, ThreadDeserializer};
+#[cfg(feature = "async")]
+pub use self::async_impl::AsyncFuture;
+pub use self::post::{LastReply, Post, SearchFields, ThreadState};
+pub use self::thread::{DownloadReport, MediaNaming, Thread, ThreadCache,
+                       ThreadDeserializer, ThreadDiff, WatchControl,
+                       WatchHandle};
 
+#[cfg(feature = "async")]
+mod async_impl;
 mod board;
 mod client;
 mod error;
+#[cfg(test)]
+mod mock;
+mod pool;
 mod post;
 mod thread;
 
-/// Define a custom If-Modified-Since header because we use `chrono::time`
-/// instead of `time:Tm` and handle date formatting with `chrono`.
-header! { (IfModifiedSince, "If-Modified-Since") => [String] }
+/// The value of an If-Modified-Since header. Dates are formatted with
+/// `chrono` rather than `time::Tm`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IfModifiedSince(pub String);
+
+impl IfModifiedSince {
+    /// Formats the date as RFC 7232 section 3.3 dictates.
+    ///
+    /// See `chrono::format::strftime` for the format specifications.
+    pub fn from_date(dt: &chrono::DateTime<chrono::UTC>) -> IfModifiedSince {
+        // If-Modified-Since: Sat, 29 Oct 1994 19:43:31 GMT
+        //                    %a,  %d %b  %Y   %T       GMT
+        IfModifiedSince(dt.format("%a, %d %b %Y %T GMT").to_string())
+    }
+}
+
+/// Get the date of the Last-Modified header of a response, so that the next
+/// If-Modified-Since is in the server's clock. Falls back to the current time
+/// if the header is missing or malformed.
+fn last_modified(headers: &reqwest::header::HeaderMap)
+    -> chrono::DateTime<chrono::UTC> {
+    headers.get(reqwest::header::LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok())
+        .map(|dt| dt.with_timezone(&chrono::UTC))
+        .unwrap_or_else(chrono::UTC::now)
+}
+
+/// Get the ETag header of a response, if any.
+fn etag(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    headers.get(reqwest::header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+/// Reads the body of a response as text, see `text`.
+#[allow(deprecated)]
+fn read_text(res: &mut reqwest::Response) -> Result<String> {
+    let mut buf = Vec::new();
+    try!(res.read_to_end(&mut buf));
+    Ok(text(buf, res.url().as_str()))
+}
+
+/// Converts the body of a response from the url to text. Invalid UTF-8 is
+/// replaced by U+FFFD rather than failing, so that one malformed post doesn't
+/// make a whole thread or catalog unreadable.
+fn text(buf: Vec<u8>, url: &str) -> String {
+    String::from_utf8(buf).unwrap_or_else(|e| {
+        warn!("Response of {} is not valid UTF-8", url);
+        String::from_utf8_lossy(e.as_bytes()).into_owned()
+    })
+}
+
+/// Sleeps for the interval of a watch, waking up regularly to return early if
+/// the handle is stopped. Returns `false` if it was.
+fn sleep_unless_stopped(interval: std::time::Duration,
+                        handle: &WatchHandle) -> bool {
+    let step = std::time::Duration::from_millis(100);
+    let start = std::time::Instant::now();
+    while !handle.is_stopped() {
+        let elapsed = start.elapsed();
+        if elapsed >= interval {
+            return true
+        }
+        std::thread::sleep(std::cmp::min(step, interval - elapsed));
+    }
+    false
+}
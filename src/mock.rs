@@ -0,0 +1,47 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+/// Serves the raw HTTP responses in order, one per connection, on a local
+/// port. Returns the base url of the server, to pass to `set_api_base` or
+/// `media_hosts`, and a handle that joins with the requests received.
+pub fn serve<R>(responses: Vec<R>) -> (String, JoinHandle<Vec<String>>)
+    where R: AsRef<[u8]> + Send + 'static
+{
+    let mut responses = responses.into_iter();
+    let count = responses.len();
+    serve_with(count, move |_| responses.next().unwrap().as_ref().to_vec())
+}
+
+/// Serves a number of connections like `serve`, but each response is built
+/// by `f` from the raw request. `f` runs before anything is written back,
+/// so it can also act while the client waits.
+pub fn serve_with<F>(connections: usize, mut f: F)
+    -> (String, JoinHandle<Vec<String>>)
+    where F: FnMut(&str) -> Vec<u8> + Send + 'static
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for _ in 0..connections {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let len = stream.read(&mut buf).unwrap();
+            let req = String::from_utf8_lossy(&buf[..len]).into_owned();
+            let res = f(&req);
+            // The client may have given up already, e.g. after a timeout.
+            let _ = stream.write_all(&res);
+            requests.push(req);
+        }
+        requests
+    });
+    (format!("http://{}", addr), server)
+}
+
+/// Builds a raw response with the status, such as "200 OK", and the body.
+/// The connection is closed after it.
+pub fn response(status: &str, body: &str) -> Vec<u8> {
+    format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, body.len(), body).into_bytes()
+}
@@ -0,0 +1,58 @@
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+
+/// The maximum number of worker threads used to run jobs concurrently.
+pub const WORKERS: usize = 4;
+
+/// Maps `f` over the items on at most `WORKERS` threads. The results are
+/// returned in the order of the items.
+///
+/// Requests made by the jobs still go through the shared `Client`, so they
+/// are rate limited as usual. The speedup comes from overlapping everything
+/// else, like waiting on responses, reading and parsing them.
+pub fn map<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+    where T: Send + 'static,
+          R: Send + 'static,
+          F: Fn(T) -> R + Send + Sync + 'static
+{
+    let workers = cmp::min(WORKERS, items.len());
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (tx, rx) = channel();
+
+    let handles = (0..workers).map(|_| {
+        let queue = queue.clone();
+        let f = f.clone();
+        let tx = tx.clone();
+        thread::spawn(move || {
+            loop {
+                // Release the queue before running the job.
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((i, item)) => tx.send((i, f(item))).unwrap(),
+                    None => break
+                }
+            }
+        })
+    }).collect::<Vec<thread::JoinHandle<()>>>();
+    drop(tx);
+
+    let mut results = rx.iter().collect::<Vec<(usize, R)>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn map_preserves_order() {
+        let results = super::map((0..20).collect(), |i: u32| i * 2);
+        assert_eq!((0..20).map(|i| i * 2).collect::<Vec<u32>>(), results);
+    }
+}
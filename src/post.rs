@@ -1,13 +1,50 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
 
-use chrono::{DateTime, NaiveDateTime, UTC};
+use chrono::{DateTime, Duration, NaiveDateTime, UTC};
+use regex::{Captures, Regex};
+use serde_json::Value;
+
+/// The longest file name, in bytes, that common file systems accept.
+const MAX_FILENAME_LEN: usize = 255;
+
+/// Device names that Windows reserves regardless of the extension.
+const RESERVED_FILENAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"
+];
+
+lazy_static! {
+    /// A quote of a post in the same thread. Same thread quotes link to an
+    /// anchor on the current page, whereas cross-thread quotes link to
+    /// another thread's page.
+    static ref QUOTELINK: Regex =
+        Regex::new(r##"<a href="#p(\d+)" class="quotelink">"##).unwrap();
+
+    /// A link to another board, or to a post on it.
+    static ref CROSS_BOARD_LINK: Regex =
+        Regex::new(r">>>/([a-z0-9]+)/(\d+)?").unwrap();
+
+    /// A line break of a comment.
+    static ref LINE_BREAK: Regex = Regex::new(r"(?i)<br\s*/?>").unwrap();
+
+    /// Any other tag of a comment.
+    static ref TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+
+    /// A named HTML entity or a numeric character reference.
+    static ref ENTITY: Regex =
+        Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap();
+}
 
 /// A `Post` owns all the data of a post. They are stored in `Vec<Post>` in
 /// their respective `Thread`.
 ///
 /// Read more about the Posts object at https://github.com/4chan/4chan-API.
 /// Defaults are for optional fields.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct Post {
     pub no: u64,
     pub resto: u64,
@@ -25,14 +62,22 @@ pub struct Post {
     pub name: String,
     #[serde(default="default::<String>")]
     pub trip: String,
+    // Historically `sage` or an address. Absent on most posts.
+    #[serde(default="default::<String>")]
+    pub email: String,
     #[serde(default="default::<String>")]
     pub id: String,
     #[serde(default="default::<String>")]
     pub capcode: String,
-    #[serde(default="default::<String>")]
-    pub country: String,
-    #[serde(default="default::<String>")]
-    pub country_name: String,
+    // Country flags are only present on some boards.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub country_name: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub board_flag: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub flag_name: Option<String>,
     #[serde(default="default::<String>")]
     pub sub: String,
     #[serde(default="default::<String>")]
@@ -55,7 +100,7 @@ pub struct Post {
     pub tn_w: u8,
     #[serde(default="default::<u8>")]
     pub tn_h: u8,
-    #[serde(default="default::<u8>")]
+    #[serde(default="default::<u8>", rename="filedeleted")]
     pub file_deleted: u8,
     #[serde(default="default::<u8>")]
     pub spoiler: u8,
@@ -92,21 +137,25 @@ pub struct Post {
     #[serde(default="default::<u16>")]
     pub unique_ips: u16,
     #[serde(default="default::<u16>")]
-    pub tail_size: u16
+    pub tail_size: u16,
+
+    // Not part of the API, so it isn't serialized. Set on topics from the
+    // catalog, whose comment may be truncated.
+    #[serde(default="default::<bool>", skip_serializing)]
+    pub preview: bool,
+
+    // Fields the API sent that are not modeled above, kept as they are.
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>
 }
 
+#[allow(deprecated)]
 impl Post {
     /// The If-Modified-Since header requires the date last modified to be in
     /// a specific format as RFC 7232 section 3.3 dictates.
     ///
-    /// See `chrono::format::strftime` for the format specifications.
-    ///
     /// Returns Some if the post has a last_modified or None if it doesn't.
     pub fn if_modified_since(&self) -> Option<::IfModifiedSince> {
-        // If-Modified-Since: Sat, 29 Oct 1994 19:43:31 GMT
-        //                    %a,  %d %b  %Y   %T       GMT
-        let format = "%a, %d %b %Y %T GMT";
-
         if self.last_modified == 0 {
             return None
         }
@@ -114,35 +163,601 @@ impl Post {
         let dt = DateTime::<UTC>::from_utc(
             NaiveDateTime::from_timestamp(self.last_modified, 0), UTC);
 
-        let fmt_date = dt.format(&format).to_string();
+        Some(::IfModifiedSince::from_date(&dt))
+    }
+
+    /// Get a field the API sent that `Post` doesn't model yet.
+    pub fn extra(&self, key: &str) -> Option<&Value> {
+        self.extra.get(key)
+    }
+
+    /// Returns `true` if the post is a topic from the catalog rather than a
+    /// post from the thread itself. 4chan truncates long comments in the
+    /// catalog, so use `Board::get_thread` to get the full comment.
+    pub fn is_preview(&self) -> bool {
+        self.preview
+    }
+
+    /// Returns `true` if the post is the OP of its thread.
+    pub fn is_op(&self) -> bool {
+        self.resto == 0
+    }
+
+    /// Get the number of the thread the post replies to, or `None` if the
+    /// post is an OP.
+    pub fn replying_to(&self) -> Option<u64> {
+        if self.is_op() {
+            return None
+        }
+        Some(self.resto)
+    }
+
+    /// Returns `true` if the thread is stickied. Always `false` for replies.
+    pub fn is_sticky(&self) -> bool {
+        self.sticky == 1
+    }
+
+    /// Returns `true` if the thread is closed. Always `false` for replies.
+    pub fn is_closed(&self) -> bool {
+        self.closed == 1
+    }
+
+    /// Get the state of the thread of a topic, derived from its `archived`,
+    /// `closed`, `sticky`, `bumplimit` and `imagelimit` fields. Archived
+    /// threads are also closed, and closed threads may be sticky, so the
+    /// first of archived, closed and sticky wins.
+    pub fn state(&self) -> ThreadState {
+        if !self.is_op() {
+            ThreadState::NotTopic
+        } else if self.is_archived() {
+            ThreadState::Archived
+        } else if self.is_closed() {
+            ThreadState::Closed
+        } else if self.is_sticky() {
+            ThreadState::Sticky
+        } else {
+            ThreadState::Active {
+                bump_limit: self.at_bump_limit(),
+                image_limit: self.at_image_limit()
+            }
+        }
+    }
+
+    /// Returns `true` if the thread reached its bump limit, so new replies
+    /// no longer bump it. Always `false` for replies.
+    pub fn at_bump_limit(&self) -> bool {
+        self.bumplimit == 1
+    }
+
+    /// Returns `true` if the thread reached its image limit, so no more
+    /// files can be posted. Always `false` for replies.
+    pub fn at_image_limit(&self) -> bool {
+        self.imagelimit == 1
+    }
+
+    /// Returns `true` if the thread is archived. Always `false` for replies.
+    pub fn is_archived(&self) -> bool {
+        self.archived == 1
+    }
+
+    /// Get the number of replies in the thread. Only topics carry it, so
+    /// this is `None` for replies.
+    pub fn reply_count(&self) -> Option<u32> {
+        if !self.is_op() {
+            return None
+        }
+        Some(self.replies)
+    }
+
+    /// Get the number of images in the replies of the thread. Only topics
+    /// carry it, so this is `None` for replies.
+    pub fn image_count(&self) -> Option<u32> {
+        if !self.is_op() {
+            return None
+        }
+        Some(self.images)
+    }
+
+    /// Get the number of unique posters in the thread, if the server sent
+    /// it. Only topics carry it.
+    pub fn unique_ips(&self) -> Option<u32> {
+        if self.unique_ips == 0 {
+            return None
+        }
+        Some(self.unique_ips as u32)
+    }
+
+    /// Get the SEO slug of the thread, if the server sent it. Only topics
+    /// carry it.
+    pub fn semantic_url(&self) -> Option<&str> {
+        non_empty(&self.semantic_url)
+    }
+
+    /// Get the url of the thread of the post on the board. The slug of the
+    /// thread is used when available, and replies link to their anchor.
+    pub fn thread_url(&self, board_name: &str) -> String {
+        match self.canonical_url(board_name) {
+            Some(url) => url,
+            None => format!("https://boards.4chan.org/{}/thread/{}#p{}",
+                            board_name, self.resto, self.no)
+        }
+    }
+
+    /// Get the link 4chan serves the thread of a topic at, or `None` if the
+    /// post is a reply, as replies don't carry the slug of their thread.
+    ///
+    /// The slug is percent-decoded and normalized the way 4chan builds them:
+    /// lowercase ascii letters and digits separated by single dashes. The url
+    /// has no slug if the server sent none or nothing is left of it.
+    pub fn canonical_url(&self, board_name: &str) -> Option<String> {
+        if !self.is_op() {
+            return None
+        }
+        let slug = normalize_slug(&self.semantic_url);
+        Some(if slug.is_empty() {
+            format!("https://boards.4chan.org/{}/thread/{}",
+                    board_name, self.no)
+        } else {
+            format!("https://boards.4chan.org/{}/thread/{}/{}",
+                    board_name, self.no, slug)
+        })
+    }
+
+    /// Get the year the poster bought their 4chan Pass, if they chose to
+    /// show it.
+    pub fn since4pass(&self) -> Option<u16> {
+        if self.since4pass == 0 {
+            return None
+        }
+        Some(self.since4pass)
+    }
+
+    /// Get the ISO 3166-1 alpha-2 country code of the poster's flag, if the
+    /// board shows flags.
+    pub fn country_code(&self) -> Option<&str> {
+        self.country.as_deref()
+    }
+
+    /// Get the name of the country of the poster's flag, if the board shows
+    /// flags.
+    pub fn country_name(&self) -> Option<&str> {
+        self.country_name.as_deref()
+    }
+
+    /// Get the tripcode of the poster, if they used one.
+    pub fn tripcode(&self) -> Option<&str> {
+        non_empty(&self.trip)
+    }
+
+    /// Get the email field of the post, if the poster filled it.
+    pub fn email(&self) -> Option<&str> {
+        non_empty(&self.email)
+    }
+
+    /// Returns `true` if the post was saged, that is if its email field is
+    /// `sage` in any case, so that it didn't bump the thread.
+    pub fn is_sage(&self) -> bool {
+        self.email.eq_ignore_ascii_case("sage")
+    }
+
+    /// Get the ID of the poster within the thread, if the board shows IDs.
+    pub fn poster_id(&self) -> Option<&str> {
+        non_empty(&self.id)
+    }
 
-        Some(::IfModifiedSince(fmt_date))
+    /// Get the capcode of the post, such as `"mod"`, `"admin"` or
+    /// `"developer"`, if it was made by staff.
+    pub fn capcode(&self) -> Option<&str> {
+        non_empty(&self.capcode)
     }
 
+    /// Get the name of the poster the way 4chan shows it: the name, or
+    /// `Anonymous` if there is none, followed by the tripcode and the
+    /// capcode, as in `Anonymous !!trip ## Mod`. Entities in the name are
+    /// decoded.
+    pub fn display_name(&self) -> String {
+        let mut name = match non_empty(&self.name) {
+            Some(name) => decode_entities(name),
+            None => "Anonymous".to_string()
+        };
+        if let Some(trip) = self.tripcode() {
+            name.push(' ');
+            name.push_str(trip);
+        }
+        if let Some(capcode) = self.capcode() {
+            // Highlighted capcodes such as `admin_highlight` show as their
+            // plain capcode.
+            let capcode = capcode.split('_').next().unwrap_or(capcode);
+            let mut chars = capcode.chars();
+            if let Some(first) = chars.next() {
+                name.push_str(" ## ");
+                name.extend(first.to_uppercase());
+                name.push_str(chars.as_str());
+            }
+        }
+        name
+    }
+
+    /// Get when the post was made.
+    pub fn timestamp(&self) -> DateTime<UTC> {
+        DateTime::<UTC>::from_utc(
+            NaiveDateTime::from_timestamp(self.time as i64, 0), UTC)
+    }
+
+    /// Get how long ago the post was made.
+    pub fn age(&self) -> Duration {
+        UTC::now().signed_duration_since(self.timestamp())
+    }
+
+    /// Returns `true` if the regex matches the name, subject, comment or
+    /// filename. See `Board::compile_query`.
     pub fn is_match(&self, regex: &::regex::Regex) -> bool {
-        regex.is_match(&self.name) ||
-            regex.is_match(&self.sub) ||
-            regex.is_match(&self.com) ||
-            regex.is_match(&self.filename)
+        self.is_match_in(regex, SearchFields::all())
+    }
+
+    /// Returns `true` if the regex matches any of the given fields.
+    pub fn is_match_in(&self, regex: &::regex::Regex, fields: SearchFields)
+        -> bool {
+        (fields.name && regex.is_match(&self.name)) ||
+            (fields.subject && regex.is_match(&self.sub)) ||
+            (fields.comment && regex.is_match(&self.com)) ||
+            (fields.filename && regex.is_match(&self.filename))
+    }
+
+    /// Get the numbers of the posts in the same thread that this post quotes,
+    /// in order of first appearance. Quotes to posts in other threads or on
+    /// other boards are excluded.
+    pub fn quoted_posts(&self) -> Vec<u64> {
+        let mut quoted = Vec::new();
+        for cap in QUOTELINK.captures_iter(&self.com) {
+            if let Ok(no) = cap[1].parse::<u64>() {
+                if !quoted.contains(&no) {
+                    quoted.push(no);
+                }
+            }
+        }
+        quoted
+    }
+
+    /// Get the links to other boards in the comment, as pairs of board name
+    /// and post number, in order of first appearance. Links to a board
+    /// rather than a post, like `>>>/g/`, have the post number 0.
+    ///
+    /// Both links 4chan turned into anchors and dead links left as text are
+    /// found, since both read `>>>/board/no`.
+    pub fn cross_board_links(&self) -> Vec<(String, u64)> {
+        let text = match self.comment_text() {
+            Some(text) => text,
+            None => return Vec::new()
+        };
+        let mut links = Vec::new();
+        for cap in CROSS_BOARD_LINK.captures_iter(&text) {
+            let no = cap.get(2)
+                .and_then(|no| no.as_str().parse::<u64>().ok())
+                .unwrap_or(0);
+            let link = (cap[1].to_string(), no);
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+        links
     }
 
+    /// Get the subject as plain text, with HTML entities decoded.
+    ///
+    /// Returns `None` if the post has no subject.
+    pub fn subject_text(&self) -> Option<String> {
+        non_empty(&self.sub).map(decode_entities)
+    }
+
+    /// Get the comment as plain text. Line breaks are turned into newlines,
+    /// tags are stripped and HTML entities are decoded. Greentext lines are
+    /// left as they are, starting with `>`, and spoilers are kept as text.
+    ///
+    /// Decoding is lossy and never fails: numeric entities that aren't valid
+    /// characters, such as lone surrogates, become U+FFFD. Invalid UTF-8 in
+    /// responses is replaced the same way when they are read.
+    ///
+    /// Returns `None` if the post has no comment.
+    pub fn comment_text(&self) -> Option<String> {
+        if self.com.is_empty() {
+            return None
+        }
+
+        let text = LINE_BREAK.replace_all(&self.com, "\n");
+        let text = TAG.replace_all(&text, "");
+
+        Some(decode_entities(&text))
+    }
+
+    /// Get the url of the full resolution file attached to the post, or
+    /// `None` if the post has no file or the file was deleted.
     pub fn image_url(&self, board_name: &str) -> Option<String> {
-        if self.filename.is_empty() || self.ext.is_empty() {
+        self.image_path(board_name)
+            .map(|path| format!("https://i.4cdn.org{}", path))
+    }
+
+    /// Get the url of the thumbnail of the file attached to the post, or
+    /// `None` if the post has no file or the file was deleted.
+    pub fn thumbnail_url(&self, board_name: &str) -> Option<String> {
+        self.thumbnail_path(board_name)
+            .map(|path| format!("https://i.4cdn.org{}", path))
+    }
+
+    /// Returns `true` if the file attached to the post is spoilered.
+    pub fn is_spoiler(&self) -> bool {
+        self.has_file() && self.spoiler == 1
+    }
+
+    /// Get the url of the image 4chan shows in place of the thumbnail of a
+    /// spoilered file, or `None` if the file isn't spoilered. Boards with
+    /// custom spoilers have their own images.
+    pub fn spoiler_thumbnail_url(&self, board_name: &str) -> Option<String> {
+        if !self.is_spoiler() {
             return None
         }
-        Some(format!("https://i.4cdn.org/{}/{}{}",
-                     board_name, self.tim, self.ext))
+        if self.custom_spoiler == 0 {
+            return Some("https://s.4cdn.org/image/spoiler.png".to_string())
         }
+        Some(format!("https://s.4cdn.org/image/spoiler-{}{}.png",
+                     board_name, self.custom_spoiler))
+    }
+
+    /// Get the extension of the file attached to the post without the
+    /// leading dot, such as `"png"`, or `None` if the post has no file or
+    /// the file was deleted.
+    pub fn extension(&self) -> Option<&str> {
+        if !self.has_file() {
+            return None
+        }
+        Some(self.ext.trim_start_matches('.'))
+    }
+
+    /// Get the name the file attached to the post had when it was uploaded,
+    /// or `None` if the post has no file or the file was deleted.
+    pub fn original_filename(&self) -> Option<String> {
+        if !self.has_file() {
+            return None
+        }
+        Some(format!("{}{}", self.filename, self.ext))
+    }
+
+    /// Get the original file name made safe to write on any platform, or
+    /// `None` if the post has no file or the file was deleted.
+    ///
+    /// Path separators, control characters and characters Windows rejects
+    /// are replaced by `_`, trailing dots and spaces are removed, reserved
+    /// device names are prefixed with `_` and the name is shortened to fit
+    /// in 255 bytes. The extension is kept, unless it is too long by itself.
+    /// A name left empty is replaced by the `tim` of the post.
+    pub fn safe_filename(&self) -> Option<String> {
+        self.safe_filename_prefixed("")
+    }
+
+    /// Get the file name like `safe_filename`, with a prefix before it. The
+    /// name is shortened after the prefix is added, so that it still fits.
+    pub(crate) fn safe_filename_prefixed(&self, prefix: &str)
+        -> Option<String> {
+        if !self.has_file() {
+            return None
+        }
+
+        let mut stem: String = self.filename.chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c
+            })
+            .collect();
+        let trimmed = stem.trim_end_matches(&['.', ' '][..]).len();
+        stem.truncate(trimmed);
+        if stem.is_empty() {
+            stem = self.tim.to_string();
+        }
+        if RESERVED_FILENAMES.iter().any(|r| r.eq_ignore_ascii_case(&stem)) {
+            stem.insert(0, '_');
+        }
+        stem.insert_str(0, prefix);
+
+        let mut ext = self.ext.clone();
+        truncate_bytes(&mut ext, MAX_FILENAME_LEN);
+        truncate_bytes(&mut stem, MAX_FILENAME_LEN - ext.len());
+        Some(format!("{}{}", stem, ext))
+    }
+
+    /// Returns `true` if the post had a file that was deleted by a moderator.
+    pub fn file_deleted(&self) -> bool {
+        self.file_deleted == 1
+    }
+
+    /// Returns `true` if the post has a file that wasn't deleted.
+    pub fn has_file(&self) -> bool {
+        !self.filename.is_empty() && !self.ext.is_empty() &&
+            !self.file_deleted()
+    }
+
+    /// Get the size in bytes of the file attached to the post, or `None` if
+    /// the post has no file or the file was deleted.
+    pub fn file_size(&self) -> Option<u32> {
+        if !self.has_file() {
+            return None
+        }
+        Some(self.fsize)
+    }
+
+    /// Get the width and height of the file attached to the post, or `None`
+    /// if the post has no file or the file was deleted.
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        if !self.has_file() {
+            return None
+        }
+        Some((self.w as u32, self.h as u32))
+    }
+
+    /// Get the width and height of the thumbnail of the file attached to the
+    /// post, or `None` if the post has no file or the file was deleted.
+    pub fn thumbnail_dimensions(&self) -> Option<(u32, u32)> {
+        if !self.has_file() {
+            return None
+        }
+        Some((self.tn_w as u32, self.tn_h as u32))
+    }
+
+    /// Get the path of the attached file relative to the media base url.
+    fn image_path(&self, board_name: &str) -> Option<String> {
+        if !self.has_file() {
+            return None
+        }
+        Some(format!("/{}/{}{}", board_name, self.tim, self.ext))
+    }
+
+    /// Get the path of the thumbnail relative to the media base url.
+    /// Thumbnails are always JPEG, whatever the extension of the file.
+    fn thumbnail_path(&self, board_name: &str) -> Option<String> {
+        if !self.has_file() {
+            return None
+        }
+        Some(format!("/{}/{}s.jpg", board_name, self.tim))
+    }
+
+    /// Downloads the full resolution file attached to the post into the
+    /// writer. The file is streamed rather than buffered in memory.
+    ///
+    /// Returns the number of bytes written, `Error::FileDeleted` if the file
+    /// was deleted, or `Error::NoAttachment` if the post has no file.
+    pub fn download_image<W: Write>(&self,
+                                    board_name: &str,
+                                    client: &Arc<Mutex<::Client>>,
+                                    out: &mut W) -> ::Result<u64> {
+        if self.file_deleted() {
+            return Err(::Error::FileDeleted)
+        }
+        match self.image_path(board_name) {
+            Some(path) => download(&path, client, out),
+            None => Err(::Error::NoAttachment)
+        }
+    }
+
+    /// Downloads the thumbnail of the file attached to the post into the
+    /// writer. Thumbnails are always JPEG, whatever the extension of the
+    /// original file.
+    ///
+    /// Returns the number of bytes written, `Error::FileDeleted` if the file
+    /// was deleted, or `Error::NoAttachment` if the post has no file.
+    pub fn download_thumbnail<W: Write>(&self,
+                                        board_name: &str,
+                                        client: &Arc<Mutex<::Client>>,
+                                        out: &mut W) -> ::Result<u64> {
+        if self.file_deleted() {
+            return Err(::Error::FileDeleted)
+        }
+        match self.thumbnail_path(board_name) {
+            Some(path) => download(&path, client, out),
+            None => Err(::Error::NoAttachment)
+        }
+    }
+
+    /// Checks that the data matches the base64 encoded MD5 digest of the
+    /// file attached to the post.
+    pub fn verify_md5(&self, data: &[u8]) -> ::Result<bool> {
+        if self.md5.is_empty() {
+            return Err(::Error::NoAttachment)
+        }
+        let expected = try!(::base64::decode(&self.md5));
+        Ok(expected[..] == ::md5::compute(data).0[..])
+    }
+
+    /// Downloads the full resolution file like `download_image`, but checks
+    /// its MD5 digest before writing anything out. The file is buffered in
+    /// memory to do so.
+    ///
+    /// Returns `Error::ChecksumMismatch` if the digests don't match.
+    pub fn download_image_verified<W: Write>(&self,
+                                             board_name: &str,
+                                             client: &Arc<Mutex<::Client>>,
+                                             out: &mut W) -> ::Result<u64> {
+        let mut buf = Vec::new();
+        let len = try!(self.download_image(board_name, client, &mut buf));
+        if !try!(self.verify_md5(&buf)) {
+            return Err(::Error::ChecksumMismatch)
+        }
+        try!(out.write_all(&buf));
+        Ok(len)
+    }
 }
 
+/// Prints a readable summary of the post: the subject, display name, number
+/// and time, followed by the comment. Text is decoded like `subject_text` and
+/// `comment_text`.
+#[allow(deprecated)]
 impl fmt::Display for Post {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "no: {}, last_modified: {}", self.no, self.last_modified)
+        if let Some(subject) = self.subject_text() {
+            try!(write!(f, "{} ", subject));
+        }
+        try!(write!(f, "{} No.{} {}", self.display_name(), self.no,
+                    self.timestamp()));
+        match self.comment_text() {
+            Some(text) => write!(f, "\n{}", text),
+            None => Ok(())
+        }
+    }
+}
+
+/// The state of a thread, see `Post::state`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThreadState {
+    // The post is a reply, not a topic.
+    NotTopic,
+    // The thread is live, possibly at its bump limit or image limit.
+    Active { bump_limit: bool, image_limit: bool },
+    // The thread is stickied.
+    Sticky,
+    // The thread is closed to new replies.
+    Closed,
+    // The thread is archived.
+    Archived
+}
+
+/// The fields of a `Post` that a search matches against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchFields {
+    pub name: bool,
+    pub subject: bool,
+    pub comment: bool,
+    pub filename: bool
+}
+
+impl SearchFields {
+    /// Search every field.
+    pub fn all() -> SearchFields {
+        SearchFields {
+            name: true,
+            subject: true,
+            comment: true,
+            filename: true
+        }
+    }
+
+    /// Search no field. Use it to enable only the fields you want.
+    pub fn none() -> SearchFields {
+        SearchFields {
+            name: false,
+            subject: false,
+            comment: false,
+            filename: false
+        }
+    }
+}
+
+impl Default for SearchFields {
+    fn default() -> SearchFields {
+        SearchFields::all()
     }
 }
 
 /// A `LastReply` is an abridged form of a `Post` given by a catalog.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct LastReply {
     pub no: u64,
     pub now: String,
@@ -154,29 +769,115 @@ pub struct LastReply {
     pub resto: u64
 }
 
-impl Default for LastReply {
-    fn default() -> LastReply {
-        LastReply {
-            no: 0,
-            now: String::new(),
-            name: String::new(),
-            com: String::new(),
-            time: 0,
-            resto: 0
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct CapcodeReplies {
+    #[serde(default="default::<Vec<u64>>")]
+    admin: Vec<u64>
+}
+
+/// Shortens the string to at most `max` bytes, on a character boundary.
+fn truncate_bytes(s: &mut String, max: usize) {
+    while s.len() > max {
+        s.pop();
+    }
+}
+
+/// Decodes the named HTML entities 4chan uses as well as numeric character
+/// references. Unknown entities are left as they are.
+fn decode_entities(text: &str) -> String {
+    ENTITY.replace_all(text, |cap: &Captures| {
+        let decoded = match &cap[1] {
+            "gt" => Some('>'),
+            "lt" => Some('<'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            // Code points that aren't characters, or don't even fit, are
+            // replaced rather than kept as entities.
+            e if e.starts_with("#x") || e.starts_with("#X") => {
+                Some(u32::from_str_radix(&e[2..], 16).ok()
+                     .and_then(::std::char::from_u32)
+                     .unwrap_or('\u{fffd}'))
+            },
+            e if e.starts_with('#') => {
+                Some(e[1..].parse::<u32>().ok()
+                     .and_then(::std::char::from_u32)
+                     .unwrap_or('\u{fffd}'))
+            },
+            _ => None
+        };
+
+        match decoded {
+            Some(c) => c.to_string(),
+            None => cap[0].to_string()
+        }
+    }).into_owned()
+}
+
+/// Percent-decodes the slug of a thread and reduces it to lowercase ascii
+/// alphanumeric words joined by dashes.
+fn normalize_slug(slug: &str) -> String {
+    let bytes = slug.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| ::std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
         }
     }
+
+    String::from_utf8_lossy(&decoded)
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
 }
 
-#[derive(Clone, Debug, Deserialize)]
-pub struct CapcodeReplies {
-    #[serde(default="default::<Vec<u64>>")]
-    admin: Vec<u64>
+/// Treats the empty strings of defaulted fields as absent.
+fn non_empty(s: &str) -> Option<&str> {
+    if s.is_empty() { None } else { Some(s) }
 }
 
-impl Default for CapcodeReplies {
-    fn default() -> CapcodeReplies {
-        CapcodeReplies { admin: Vec::new() }
+/// Streams the response of a GET request to the media path into the writer.
+/// The media hosts of the client are tried in order until one of them
+/// doesn't fail with a server or connection error.
+#[allow(deprecated)]
+fn download<W: Write>(path: &str,
+                      client: &Arc<Mutex<::Client>>,
+                      out: &mut W) -> ::Result<u64> {
+    // The client is only locked to get what the requests need.
+    let (urls, sender) = {
+        let client = client.lock().unwrap();
+        (client.media_urls(path), client.sender())
+    };
+    let mut last_error = None;
+    for url in urls {
+        let res = sender.get_media(&url);
+        let error = match res {
+            Ok(ref res) if res.status().is_server_error() => {
+                ::Error::UnexpectedStatus(res.status())
+            },
+            Ok(ref res) if !res.status().is_success() => {
+                return Err(::Error::UnexpectedStatus(res.status()))
+            },
+            Ok(mut res) => return Ok(try!(::std::io::copy(&mut res, out))),
+            Err(e) => e
+        };
+        warn!("Failed to download {}: {}", url, error);
+        last_error = Some(error);
     }
+    Err(last_error.unwrap())
 }
 
 /// Returns the default of a type that implements `Default`.
@@ -186,6 +887,8 @@ fn default<T: Default>() -> T {
 
 #[cfg(test)]
 mod test {
+    use std::sync::{Arc, Mutex};
+
     #[test]
     fn post_if_modified_since_test() {
         let post = ::Post {
@@ -199,10 +902,13 @@ mod test {
             time: 0,
             name: String::new(),
             trip: String::new(),
+            email: String::new(),
             id: String::new(),
             capcode: String::new(),
-            country: String::new(),
-            country_name: String::new(),
+            country: None,
+            country_name: None,
+            board_flag: None,
+            flag_name: None,
             sub: String::new(),
             com: String::new(),
             tim: 0,
@@ -230,10 +936,436 @@ mod test {
             since4pass: 0,
             last_replies: Vec::new(),
             unique_ips: 0,
-            tail_size: 0
+            tail_size: 0,
+            preview: false,
+            extra: ::std::collections::HashMap::new()
         };
 
         let ims = post.if_modified_since().unwrap();
         assert_eq!("Sat, 15 Apr 2017 01:03:25 GMT", &ims.0);
     }
+
+    #[test]
+    fn post_timestamp_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "04/15/17(Sat)01:03", "time": 1492218205
+        }"#).unwrap();
+
+        assert_eq!("2017-04-15 01:03:25 UTC", post.timestamp().to_string());
+        assert!(post.age() > ::chrono::Duration::days(365));
+    }
+
+    #[test]
+    fn post_counts_test() {
+        let topic: ::Post = ::serde_json::from_str(r#"{
+            "no": 60857183, "now": "04/14/17(Fri)21:03:25",
+            "name": "Anonymous", "sub": "/dpt/ - Daily Programming Thread",
+            "com": "What are you working on?",
+            "filename": "1491862374209", "ext": ".png", "w": 1920, "h": 1080,
+            "tn_w": 250, "tn_h": 140, "tim": 1492218205123, "time": 1492218205,
+            "md5": "7VQsdLy3u5WKzIuB+7Sd9w==", "fsize": 152840, "resto": 0,
+            "bumplimit": 0, "imagelimit": 0,
+            "semantic_url": "dpt-daily-programming-thread",
+            "replies": 120, "images": 45, "omitted_posts": 115,
+            "omitted_images": 44, "last_modified": 1492226011,
+            "last_replies": [{
+                "no": 60858017, "now": "04/14/17(Fri)23:13:31",
+                "name": "Anonymous", "com": "Rust", "time": 1492226011,
+                "resto": 60857183
+            }]
+        }"#).unwrap();
+        assert!(topic.is_op());
+        assert_eq!(None, topic.replying_to());
+        assert_eq!(Some(120), topic.reply_count());
+        assert_eq!(Some(45), topic.image_count());
+        assert!(!topic.at_bump_limit());
+        assert!(!topic.at_image_limit());
+        assert_eq!(None, topic.unique_ips());
+        assert_eq!(Some("dpt-daily-programming-thread"), topic.semantic_url());
+        assert_eq!(concat!("https://boards.4chan.org/g/thread/60857183/",
+                           "dpt-daily-programming-thread"),
+                   topic.thread_url("g"));
+
+        let reply: ::Post = ::serde_json::from_str(r#"{
+            "no": 60858017, "now": "04/14/17(Fri)23:13:31",
+            "name": "Anonymous", "com": "Rust", "time": 1492226011,
+            "resto": 60857183
+        }"#).unwrap();
+        assert!(!reply.is_op());
+        assert_eq!(Some(60857183), reply.replying_to());
+        assert!(!reply.at_bump_limit());
+        assert!(!reply.at_image_limit());
+        assert_eq!(None, reply.reply_count());
+        assert_eq!(None, reply.image_count());
+        assert_eq!(None, reply.semantic_url());
+        assert_eq!("https://boards.4chan.org/g/thread/60857183#p60858017",
+                   reply.thread_url("g"));
+    }
+
+    #[test]
+    fn post_canonical_url_test() {
+        let mut topic: ::Post = ::serde_json::from_str(r#"{
+            "no": 60857183, "now": "04/14/17(Fri)21:03:25",
+            "sub": "/dpt/ - Daily Programming Thread", "time": 1492218205,
+            "resto": 0, "semantic_url": "dpt-daily-programming-thread"
+        }"#).unwrap();
+        assert_eq!(Some(concat!("https://boards.4chan.org/g/thread/60857183/",
+                                "dpt-daily-programming-thread").to_string()),
+                   topic.canonical_url("g"));
+
+        topic.semantic_url = " DPT%2Fdaily--Programming%20Thread ".to_string();
+        assert_eq!(Some(concat!("https://boards.4chan.org/g/thread/60857183/",
+                                "dpt-daily-programming-thread").to_string()),
+                   topic.canonical_url("g"));
+
+        topic.semantic_url = "%%".to_string();
+        assert_eq!(Some("https://boards.4chan.org/g/thread/60857183"
+                            .to_string()),
+                   topic.canonical_url("g"));
+
+        topic.resto = 1;
+        assert_eq!(None, topic.canonical_url("g"));
+    }
+
+    #[test]
+    fn post_limits_test() {
+        let topic: ::Post = ::serde_json::from_str(r#"{
+            "no": 60857183, "resto": 0, "now": "", "time": 1492218205,
+            "replies": 310, "images": 151, "bumplimit": 1, "imagelimit": 1
+        }"#).unwrap();
+        assert!(topic.at_bump_limit());
+        assert!(topic.at_image_limit());
+        assert_eq!(::ThreadState::Active {
+            bump_limit: true,
+            image_limit: true
+        }, topic.state());
+
+        let topic: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0, "sticky": 1,
+            "closed": 1
+        }"#).unwrap();
+        assert_eq!(::ThreadState::Closed, topic.state());
+
+        let reply: ::Post = ::serde_json::from_str(r#"{
+            "no": 2, "resto": 1, "now": "", "time": 0
+        }"#).unwrap();
+        assert_eq!(::ThreadState::NotTopic, reply.state());
+    }
+
+    #[test]
+    fn post_email_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 2, "resto": 1, "now": "", "time": 0, "email": "SAGE"
+        }"#).unwrap();
+        assert_eq!(Some("SAGE"), post.email());
+        assert!(post.is_sage());
+        assert_eq!(None, post.extra("email"));
+
+        let json = ::serde_json::to_string(&post).unwrap();
+        let post: ::Post = ::serde_json::from_str(&json).unwrap();
+        assert!(post.is_sage());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 3, "resto": 1, "now": "", "time": 0
+        }"#).unwrap();
+        assert_eq!(None, post.email());
+        assert!(!post.is_sage());
+    }
+
+    #[test]
+    fn post_extra_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0, "m_img": 1
+        }"#).unwrap();
+        assert_eq!(Some(&::serde_json::Value::from(1)), post.extra("m_img"));
+        assert_eq!(None, post.extra("no"));
+
+        let json = ::serde_json::to_string(&post).unwrap();
+        let post: ::Post = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(Some(&::serde_json::Value::from(1)), post.extra("m_img"));
+    }
+
+    #[test]
+    fn post_display_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 1492218205,
+            "name": "Anonymous", "sub": "Thread", "com": "first &amp; best"
+        }"#).unwrap();
+        assert_eq!("Thread Anonymous No.1 2017-04-15 01:03:25 UTC\n\
+                    first & best", post.to_string());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 2, "resto": 1, "now": "", "time": 1492218205,
+            "trip": "!Ep8pui8Vw2", "sub": "Rust &amp; Go"
+        }"#).unwrap();
+        assert_eq!("Rust & Go Anonymous !Ep8pui8Vw2 No.2 \
+                    2017-04-15 01:03:25 UTC", post.to_string());
+    }
+
+    #[test]
+    fn post_country_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0,
+            "country": "FI", "country_name": "Finland"
+        }"#).unwrap();
+        assert_eq!(Some("FI"), post.country_code());
+        assert_eq!(Some("Finland"), post.country_name());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0
+        }"#).unwrap();
+        assert_eq!(None, post.country_code());
+        assert_eq!(None, post.country_name());
+    }
+
+    #[test]
+    fn post_display_name_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0
+        }"#).unwrap();
+        assert_eq!("Anonymous", post.display_name());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 2, "resto": 1, "now": "", "time": 0,
+            "name": "Ferris &amp; co", "trip": "!!Rust4Life"
+        }"#).unwrap();
+        assert_eq!("Ferris & co !!Rust4Life", post.display_name());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 3, "resto": 1, "now": "", "time": 0,
+            "name": "Anonymous", "trip": "!Ep8pui8Vw2", "capcode": "mod"
+        }"#).unwrap();
+        assert_eq!("Anonymous !Ep8pui8Vw2 ## Mod", post.display_name());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 4, "resto": 1, "now": "", "time": 0,
+            "name": "moot", "capcode": "admin_highlight"
+        }"#).unwrap();
+        assert_eq!("moot ## Admin", post.display_name());
+    }
+
+    #[test]
+    fn post_capcode_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0, "name": "Anonymous",
+            "trip": "!!Ep5GyFGhRzj", "id": "Bg2bH4Ao", "capcode": "mod",
+            "since4pass": 2015
+        }"#).unwrap();
+        assert_eq!(Some(2015), post.since4pass());
+        assert_eq!(Some("!!Ep5GyFGhRzj"), post.tripcode());
+        assert_eq!(Some("Bg2bH4Ao"), post.poster_id());
+        assert_eq!(Some("mod"), post.capcode());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0
+        }"#).unwrap();
+        assert_eq!(None, post.tripcode());
+        assert_eq!(None, post.poster_id());
+        assert_eq!(None, post.capcode());
+        assert_eq!(None, post.since4pass());
+    }
+
+    #[test]
+    fn post_media_urls_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0,
+            "filename": "wallpaper", "ext": ".png", "tim": 1492218205123,
+            "fsize": 1024, "w": 1920, "h": 1080
+        }"#).unwrap();
+        assert_eq!(Some("https://i.4cdn.org/g/1492218205123.png".to_string()),
+                   post.image_url("g"));
+        assert_eq!(Some("https://i.4cdn.org/g/1492218205123s.jpg".to_string()),
+                   post.thumbnail_url("g"));
+        assert_eq!(Some("wallpaper.png".to_string()), post.original_filename());
+        assert_eq!(Some("png"), post.extension());
+        assert_eq!(Some(1024), post.file_size());
+        assert_eq!(Some((1920, 1080)), post.dimensions());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 2, "resto": 1, "now": "", "time": 0
+        }"#).unwrap();
+        assert_eq!(None, post.image_url("g"));
+        assert_eq!(None, post.thumbnail_url("g"));
+        assert_eq!(None, post.original_filename());
+        assert_eq!(None, post.extension());
+        assert_eq!(None, post.file_size());
+        assert_eq!(None, post.dimensions());
+    }
+
+    #[test]
+    fn post_safe_filename_test() {
+        let mut post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0,
+            "filename": "../etc/pass:wd. ", "ext": ".png",
+            "tim": 1492218205123
+        }"#).unwrap();
+        assert_eq!(Some(".._etc_pass_wd.png".to_string()),
+                   post.safe_filename());
+
+        post.filename = "con".to_string();
+        assert_eq!(Some("_con.png".to_string()), post.safe_filename());
+
+        post.filename = ". . .".to_string();
+        assert_eq!(Some("1492218205123.png".to_string()),
+                   post.safe_filename());
+
+        post.filename = "a\u{0}b".repeat(100);
+        let name = post.safe_filename().unwrap();
+        assert_eq!(255, name.len());
+        assert!(name.starts_with("a_b") && name.ends_with(".png"));
+        let name = post.safe_filename_prefixed("1492218205123_").unwrap();
+        assert_eq!(255, name.len());
+        assert!(name.starts_with("1492218205123_a_b"));
+
+        post.ext = ".".to_string() + &"x".repeat(300);
+        assert_eq!(255, post.safe_filename().unwrap().len());
+
+        post.file_deleted = 1;
+        assert_eq!(None, post.safe_filename());
+    }
+
+    #[test]
+    fn post_spoiler_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0,
+            "filename": "spoiler", "ext": ".jpg", "tim": 1, "spoiler": 1
+        }"#).unwrap();
+        assert!(post.is_spoiler());
+        assert_eq!(Some("https://s.4cdn.org/image/spoiler.png".to_string()),
+                   post.spoiler_thumbnail_url("a"));
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0, "filename": "spoiler",
+            "ext": ".jpg", "tim": 1, "spoiler": 1, "custom_spoiler": 3
+        }"#).unwrap();
+        assert_eq!(Some("https://s.4cdn.org/image/spoiler-a3.png".to_string()),
+                   post.spoiler_thumbnail_url("a"));
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0,
+            "filename": "wallpaper", "ext": ".png", "tim": 1
+        }"#).unwrap();
+        assert!(!post.is_spoiler());
+        assert_eq!(None, post.spoiler_thumbnail_url("a"));
+    }
+
+    #[test]
+    fn post_file_deleted_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0,
+            "filename": "deleted", "ext": ".jpg", "tim": 1, "filedeleted": 1
+        }"#).unwrap();
+        assert!(post.file_deleted());
+        assert_eq!(None, post.image_url("g"));
+
+        // Fails before making any request.
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        match post.download_image("g", &client, &mut Vec::new()) {
+            Err(::Error::FileDeleted) => (),
+            res => panic!("Expected FileDeleted, got {:?}", res)
+        }
+        match post.download_thumbnail("g", &client, &mut Vec::new()) {
+            Err(::Error::FileDeleted) => (),
+            res => panic!("Expected FileDeleted, got {:?}", res)
+        }
+    }
+
+    #[test]
+    fn post_download_falls_back_test() {
+        // Each host serves a single response.
+        let serve = |status, body| ::mock::serve(vec![
+            ::mock::response(status, body)]).0;
+
+        let client = ::Client::builder()
+            .retries(0)
+            .media_hosts(vec![serve("503 Service Unavailable", ""),
+                              serve("200 OK", "image")])
+            .build()
+            .unwrap();
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0,
+            "filename": "image", "ext": ".png", "tim": 1492218205123
+        }"#).unwrap();
+
+        let mut buf = Vec::new();
+        post.download_image("g", &Arc::new(Mutex::new(client)), &mut buf)
+            .unwrap();
+        assert_eq!(b"image", &buf[..]);
+    }
+
+    #[test]
+    fn post_verify_md5_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 1, "resto": 0, "now": "", "time": 0,
+            "filename": "fox", "ext": ".txt", "tim": 1,
+            "md5": "nhB9nTcrtoJr2B01QqQZ1g=="
+        }"#).unwrap();
+
+        let data = b"The quick brown fox jumps over the lazy dog";
+        assert!(post.verify_md5(data).unwrap());
+        assert!(!post.verify_md5(b"The quick brown fox").unwrap());
+    }
+
+    #[test]
+    fn post_quoted_posts_test() {
+        let post: ::Post = ::serde_json::from_str(concat!(
+            r##"{"no": 5, "resto": 1, "now": "", "time": 0, "com": ""##,
+            r##"<a href=\"#p3\" class=\"quotelink\">&gt;&gt;3</a><br>"##,
+            r##"<a href=\"#p2\" class=\"quotelink\">&gt;&gt;2</a><br>"##,
+            r##"<a href=\"/g/thread/9#p9\" class=\"quotelink\">"##,
+            r##"&gt;&gt;9</a><br>"##,
+            r##"<a href=\"#p3\" class=\"quotelink\">&gt;&gt;3</a>"}"##))
+            .unwrap();
+        assert_eq!(vec![3, 2], post.quoted_posts());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 6, "resto": 1, "now": "", "time": 0
+        }"#).unwrap();
+        assert!(post.quoted_posts().is_empty());
+    }
+
+    #[test]
+    fn post_cross_board_links_test() {
+        let post: ::Post = ::serde_json::from_str(concat!(
+            r##"{"no": 2, "resto": 1, "now": "", "time": 0, "com": ""##,
+            r##"<a href=\"/v/thread/123#p456\" class=\"quotelink\">"##,
+            r##"&gt;&gt;&gt;/v/456</a><br>"##,
+            r##"<span class=\"deadlink\">&gt;&gt;&gt;/b/789</span><br>"##,
+            r##"<a href=\"//boards.4chan.org/g/\" class=\"quotelink\">"##,
+            r##"&gt;&gt;&gt;/g/</a><br>"##,
+            r##"<a href=\"#p1\" class=\"quotelink\">&gt;&gt;1</a>"}"##))
+            .unwrap();
+
+        assert_eq!(vec![("v".to_string(), 456), ("b".to_string(), 789),
+                        ("g".to_string(), 0)],
+                   post.cross_board_links());
+    }
+
+    #[test]
+    fn post_comment_text_test() {
+        let post: ::Post = ::serde_json::from_str(concat!(
+            r##"{"no": 2, "resto": 1, "now": "", "time": 0, "com": ""##,
+            r##"<a href=\"#p1\" class=\"quotelink\">&gt;&gt;1</a><br>"##,
+            r##"<span class=\"quote\">&gt;implying</span><br>"##,
+            r##"it&#039;s <s>a spoiler</s> &amp; &quot;long&quot;<wbr>word"}"##))
+            .unwrap();
+
+        assert_eq!(">>1\n>implying\nit's a spoiler & \"long\"word",
+                   post.comment_text().unwrap());
+
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 3, "resto": 1, "now": "", "time": 0
+        }"#).unwrap();
+        assert!(post.comment_text().is_none());
+    }
+
+    #[test]
+    fn post_comment_text_lossy_test() {
+        let post: ::Post = ::serde_json::from_str(r#"{
+            "no": 2, "resto": 1, "now": "", "time": 0,
+            "com": "bad &#xD800; &#99999999999; &#x1F980;"
+        }"#).unwrap();
+        assert_eq!("bad \u{fffd} \u{fffd} \u{1f980}",
+                   post.comment_text().unwrap());
+    }
 }
@@ -1,11 +1,20 @@
-use std::collections::HashMap;
-use std::io::Read;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
 use std::fmt;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread::sleep;
+use std::time::Duration as StdDuration;
 
-use chrono::{DateTime, Duration, UTC};
+use chrono::{DateTime, Duration, NaiveDateTime, UTC};
+#[cfg(feature = "async")]
+use futures::Future;
 use reqwest::StatusCode;
+use serde_json::Value;
 
 /// A `Thread` is a 4chan thread. Its topic is the OP `Post` and its replies
 /// are every reply in the thread.
@@ -13,24 +22,31 @@ use reqwest::StatusCode;
 pub struct Thread {
     pub board_name: String,
     client: Arc<Mutex<::Client>>,
-    pub topic: ::Post,
-    pub replies: Vec<::Post>,
+    // Every post in the thread. The first post is the OP.
+    posts: Vec<::Post>,
     pub expired: bool,
     wants_update: bool,
     last_reply_no: u64,
-    last_updated: Option<DateTime<UTC>>
+    last_updated: Option<DateTime<UTC>>,
+    // When the thread was last modified, sent as If-Modified-Since.
+    last_modified: Option<DateTime<UTC>>,
+    // The ETag of the last response, sent as If-None-Match.
+    etag: Option<String>
 }
 
+#[allow(deprecated)]
 impl Thread {
-    /// Creates a new `Thread` from a topic `Post`.
+    /// Creates a new `Thread` from a topic `Post`. The OP is marked as a
+    /// preview until the thread is updated, see `Post::is_preview`.
     pub fn from_topic(post: ::Post,
                       board_name: &str,
                       client: Arc<Mutex<::Client>>) -> Thread {
+        let mut op = post.clone();
+        op.preview = true;
         Thread {
             board_name: board_name.to_string(),
-            client: client,
-            topic: post.clone(),
-            replies: Vec::new(),
+            client,
+            posts: vec![op],
             expired: false,
             wants_update: true,
             last_reply_no: {
@@ -40,11 +56,14 @@ impl Thread {
                     0
                 }
             },
-            last_updated: None
+            last_updated: None,
+            last_modified: last_modified(&post),
+            etag: None
         }
     }
 
-    /// Creates a new `Thread` from a `ThreadDeserializer`.
+    /// Creates a new `Thread` from a `ThreadDeserializer`. The thread is
+    /// considered to have just been updated.
     pub fn from_deserializer(deserializer: ThreadDeserializer,
                              board_name: &str,
                              client: Arc<Mutex<::Client>>) -> Thread {
@@ -52,9 +71,8 @@ impl Thread {
 
         Thread {
             board_name: board_name.to_string(),
-            client: client,
-            topic: topic.clone(),
-            replies: deserializer.posts.iter().skip(1).cloned().collect(),
+            client,
+            posts: deserializer.posts,
             expired: false,
             wants_update: true,
             last_reply_no: {
@@ -64,77 +82,371 @@ impl Thread {
                     0
                 }
             },
-            last_updated: None
+            last_updated: Some(UTC::now()),
+            last_modified: last_modified(&topic),
+            etag: None
         }
     }
 
     /// Updates a `Thread`, throttling updates by 10 second intervals and
     /// using "If-Modified-Since".
-    pub fn update(&mut self) -> ::Result<()> {
-        if self.expired { return Ok(()) }
+    ///
+    /// Returns `true` if new data arrived, and `false` if the thread was not
+    /// modified or has expired.
+    pub fn update(&mut self) -> ::Result<bool> {
+        Ok(!try!(self.update_diff()).is_empty())
+    }
+
+    /// Updates a `Thread` like `update`, returning what changed. The diff is
+    /// empty if the thread was not modified or has expired.
+    pub fn update_diff(&mut self) -> ::Result<ThreadDiff> {
+        let request = match self.update_request() {
+            Some(request) => request,
+            None => return Ok(ThreadDiff::default())
+        };
+        let response = try!(request.send());
+        Ok(self.apply(response))
+    }
+
+    /// Get what an update of the thread needs, so that the request can be
+    /// made without borrowing the thread, such as while it stays in the
+    /// cache. Returns `None` if the thread has expired.
+    pub(crate) fn update_request(&self) -> Option<UpdateRequest> {
+        if self.expired { return None }
 
         // Threads should be updated no faster than every 10 seconds.
-        if self.last_updated.is_some() {
-            let diff = UTC::now()
-                .signed_duration_since(self.last_updated.unwrap());
+        let wait = self.last_updated.and_then(|last_updated| {
+            let diff = UTC::now().signed_duration_since(last_updated);
             if diff < Duration::seconds(10) {
-                sleep(try!(diff.to_std()));
+                (Duration::seconds(10) - diff).to_std().ok()
+            } else {
+                None
             }
-        }
+        });
 
-        let mut res = try!(self.client.lock().unwrap().get(
-                &format!("https://a.4cdn.org/{}/thread/{}.json",
-                         self.board_name, self.topic.no),
-                         self.topic.if_modified_since()));
+        // The client is only locked to get what the request needs.
+        let client = self.client.lock().unwrap();
+        Some(UpdateRequest {
+            url: client.api_url(&format!("/{}/thread/{}.json",
+                                         self.board_name, self.op().no)),
+            since: self.last_modified
+                .map(|dt| ::IfModifiedSince::from_date(&dt)),
+            etag: self.etag.clone(),
+            wait,
+            sender: client.sender()
+        })
+    }
 
+    /// Applies the response to an update. A response older than the thread,
+    /// such as one that raced with another update, is ignored.
+    pub(crate) fn apply(&mut self, response: UpdateResponse) -> ThreadDiff {
         self.last_updated = Some(UTC::now());
 
-        match *res.status() {
-            StatusCode::Ok => {
+        match response {
+            UpdateResponse::Modified { posts, last_modified, etag } => {
+                if self.last_modified > Some(last_modified) {
+                    debug!("Thread /{}/{} is newer than the response",
+                           self.board_name, self.op().no);
+                    return ThreadDiff::default()
+                }
                 self.wants_update = true;
-                let mut buf = String::new();
-                try!(res.read_to_string(&mut buf));
+                self.last_modified = Some(last_modified);
+                self.etag = etag;
 
-                debug!("Got response: {}", buf);
-
-                let thread: ThreadDeserializer = try!(
-                    ::serde_json::from_str(&buf));
-                self.topic = thread.posts.first().unwrap().to_owned();
-
-                if self.topic.replies > 0 {
-                    if self.last_reply_no != 0 {
-                        for post in thread.posts.iter().skip(1) {
-                            if post.no > self.last_reply_no {
-                                self.replies.push(post.to_owned());
-                            }
-                        }
-                    } else {
-                        for post in thread.posts.iter().skip(1) {
-                            self.replies.push(post.to_owned());
-                        }
-                    }
-                    if self.replies.len() > 0 {
-                        self.last_reply_no = self.replies.last().unwrap().no;
+                // Compute the diff before replacing the posts.
+                let diff = {
+                    let (old, new) = (self.replies(), &posts[1..]);
+                    let old_nos: HashSet<u64> =
+                        old.iter().map(|r| r.no).collect();
+                    let new_nos: HashSet<u64> =
+                        new.iter().map(|p| p.no).collect();
+                    ThreadDiff {
+                        new_posts: new.iter()
+                            .filter(|p| !old_nos.contains(&p.no))
+                            .cloned()
+                            .collect(),
+                        deleted: old.iter()
+                            .filter(|r| !new_nos.contains(&r.no))
+                            .map(|r| r.no)
+                            .collect(),
+                        op_changed: posts[0] != *self.op()
                     }
-                }
-                Ok(())
+                };
+
+                self.posts = posts;
+                self.last_reply_no = self.replies().last()
+                    .map_or(self.last_reply_no, |r| r.no);
+                info!("Updated thread /{}/{}: {} new, {} deleted",
+                      self.board_name, self.op().no, diff.new_posts.len(),
+                      diff.deleted.len());
+                diff
             },
-            StatusCode::NotModified => {
-                Ok(())
+            UpdateResponse::NotModified => {
+                debug!("Thread /{}/{} not modified", self.board_name,
+                       self.op().no);
+                ThreadDiff::default()
             },
-            StatusCode::NotFound => {
+            UpdateResponse::NotFound => {
+                info!("Thread /{}/{} expired", self.board_name, self.op().no);
                 self.expired = true;
                 self.wants_update = false;
                 // TODO: Delete from cache? If so, now?
                 // How long do we want to keep expired threads in cache?
-                Ok(())
+                ThreadDiff::default()
+            }
+        }
+    }
+
+    /// Returns `true` if the thread was fetched in full. Threads created from
+    /// a topic only have the OP preview until they are updated, see
+    /// `fetch_full`. A thread without replies is loaded once fetched too.
+    pub fn is_loaded(&self) -> bool {
+        !self.op().is_preview()
+    }
+
+    /// Fetches the complete thread if it is only a topic stub, such as a
+    /// thread of the catalog. Unlike `update`, the request is unconditional,
+    /// as the topic may not have been modified since the catalog was. Does
+    /// nothing if the thread is already loaded.
+    ///
+    /// Returns `Error::ThreadNotFound` if the thread expired.
+    pub fn fetch_full(&mut self) -> ::Result<()> {
+        if self.is_loaded() {
+            return Ok(())
+        }
+        self.last_modified = None;
+        self.etag = None;
+        try!(self.update_diff());
+        if self.expired {
+            return Err(::Error::ThreadNotFound(self.op().no))
+        }
+        Ok(())
+    }
+
+    /// Merges another copy of the same thread into this one, such as a copy
+    /// loaded from disk into a live one. The posts of both copies are kept,
+    /// and when both have a post the one from the copy modified last wins.
+    /// The OP with the later `last_modified` wins. The thread is expired if
+    /// either copy is.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the copies are not of the same thread.
+    pub fn merge(&mut self, other: Thread) {
+        assert_eq!(self.op().no, other.op().no, "Merging different threads");
+
+        let Thread {
+            posts: other_posts,
+            expired,
+            wants_update,
+            last_reply_no,
+            last_updated,
+            last_modified,
+            etag,
+            ..
+        } = other;
+
+        // `None` sorts before any date, so a copy that was never modified
+        // is the older one.
+        let other_newer = last_modified > self.last_modified;
+        let op = {
+            let (op, other_op) = (self.op(), &other_posts[0]);
+            if other_op.last_modified > op.last_modified ||
+                (other_op.last_modified == op.last_modified && other_newer) {
+                other_op.clone()
+            } else {
+                op.clone()
             }
-            _ => Err(::Error::UnexpectedResponse)
+        };
+
+        let own_posts = ::std::mem::take(&mut self.posts);
+        let (older, newer) = if other_newer {
+            (own_posts, other_posts)
+        } else {
+            (other_posts, own_posts)
+        };
+        // Post numbers only grow, so the OP stays first.
+        let mut posts = BTreeMap::new();
+        for post in older.into_iter().chain(newer) {
+            posts.insert(post.no, post);
+        }
+        posts.insert(op.no, op);
+        self.posts = posts.into_values().collect();
+
+        self.expired = self.expired || expired;
+        self.wants_update = self.wants_update && wants_update;
+        self.last_reply_no = cmp::max(self.last_reply_no, last_reply_no);
+        self.last_updated = cmp::max(self.last_updated, last_updated);
+        if other_newer {
+            self.last_modified = last_modified;
+            self.etag = etag;
         }
     }
 
+    /// Exports the thread as a JSON document of the form
+    /// `{"board": "g", "no": 1, "expired": false, "op": {}, "posts": []}`,
+    /// where `posts` are the replies. Unlike `ThreadCache::save`, this is
+    /// meant to be read by other tools: the posts have their own fixed set
+    /// of fields rather than those of the API, so that the schema doesn't
+    /// change with the API. A post is of the form
+    ///
+    /// ```json
+    /// {"no": 2, "reply_to": 1, "time": 1492218205, "name": "Anonymous",
+    ///  "tripcode": null, "poster_id": null, "capcode": null, "email": null,
+    ///  "country": null, "subject": null, "comment": "<b>html</b>",
+    ///  "sticky": false, "closed": false,
+    ///  "file": {"name": "image", "ext": ".png", "tim": 1492218205123,
+    ///           "size": 1024, "md5": "...", "width": 1920, "height": 1080,
+    ///           "thumbnail_width": 250, "thumbnail_height": 140,
+    ///           "spoiler": false, "deleted": false}}
+    /// ```
+    ///
+    /// where `reply_to` is 0 for the OP, the comment is the HTML sent by the
+    /// API, and `file` is `null` for posts without one.
+    pub fn to_json(&self) -> ::Result<String> {
+        let json = ThreadJson {
+            board: self.board_name.clone(),
+            no: self.op().no,
+            expired: self.expired,
+            op: PostJson::from_post(self.op()),
+            posts: self.replies().iter().map(PostJson::from_post).collect()
+        };
+        Ok(try!(::serde_json::to_string(&json)))
+    }
+
+    /// Imports a thread exported with `to_json`. The thread uses the client
+    /// to update, and is updated on the next call to `update`. Fields of the
+    /// posts that the export doesn't have are left to their defaults until
+    /// then.
+    pub fn from_json(json: &str, client: Arc<Mutex<::Client>>)
+        -> ::Result<Thread> {
+        let json: ThreadJson = try!(::serde_json::from_str(json));
+        let mut posts = vec![json.op.into_post()];
+        posts.extend(json.posts.into_iter().map(PostJson::into_post));
+        Ok(Thread {
+            board_name: json.board,
+            client,
+            last_reply_no: posts.last().unwrap().no,
+            last_modified: None,
+            posts,
+            expired: json.expired,
+            wants_update: !json.expired,
+            last_updated: None,
+            etag: None
+        })
+    }
+
+    /// Watches the thread, updating it every interval and calling `f` with
+    /// the diff of every update that changed something. Stops when `f`
+    /// returns `WatchControl::Stop` or when the thread expires.
+    ///
+    /// Updates go through the client like any other, so they are still rate
+    /// limited.
+    pub fn watch<F>(&mut self, interval: StdDuration, f: F) -> ::Result<()>
+        where F: FnMut(ThreadDiff) -> WatchControl
+    {
+        self.watch_with(interval, false, &WatchHandle::new(), f)
+    }
+
+    /// Watches the thread like `watch`, but calls `f` after every update,
+    /// even those that didn't change anything.
+    pub fn watch_all<F>(&mut self, interval: StdDuration, f: F) -> ::Result<()>
+        where F: FnMut(ThreadDiff) -> WatchControl
+    {
+        self.watch_with(interval, true, &WatchHandle::new(), f)
+    }
+
+    /// Watches the thread like `watch`, but also stops once the handle is
+    /// stopped, which can be done from another thread. The handle is checked
+    /// before every update and while sleeping between them.
+    pub fn watch_with_handle<F>(&mut self,
+                                interval: StdDuration,
+                                handle: &WatchHandle,
+                                f: F) -> ::Result<()>
+        where F: FnMut(ThreadDiff) -> WatchControl
+    {
+        self.watch_with(interval, false, handle, f)
+    }
+
+    fn watch_with<F>(&mut self,
+                     interval: StdDuration,
+                     notify_empty: bool,
+                     handle: &WatchHandle,
+                     mut f: F) -> ::Result<()>
+        where F: FnMut(ThreadDiff) -> WatchControl
+    {
+        loop {
+            if handle.is_stopped() {
+                return Ok(())
+            }
+            let diff = try!(self.update_diff());
+            if self.expired {
+                return Ok(())
+            }
+            if notify_empty || !diff.is_empty() {
+                if let WatchControl::Stop = f(diff) {
+                    return Ok(())
+                }
+            }
+            if !::sleep_unless_stopped(interval, handle) {
+                return Ok(())
+            }
+        }
+    }
+
+    /// Get the OP of the thread.
+    pub fn op(&self) -> &::Post {
+        &self.posts[0]
+    }
+
+    /// Get the OP of the thread. Replaces the public `topic` field, now that
+    /// the posts are stored together.
+    #[deprecated(note = "use `op` instead")]
+    pub fn topic(&self) -> &::Post {
+        self.op()
+    }
+
+    /// Get every reply in the thread, that is every post but the OP.
+    /// Replaces the public `replies` field.
+    pub fn replies(&self) -> &[::Post] {
+        &self.posts[1..]
+    }
+
+    /// Get every post in the thread, starting with the OP.
+    pub fn posts(&self) -> &[::Post] {
+        &self.posts
+    }
+
+    /// Get the posts made after the post numbered `after`, for rendering only
+    /// what is new since then. Every post is returned if `after` is not in
+    /// the thread, for instance because it was deleted.
+    pub fn posts_since(&self, after: u64) -> &[::Post] {
+        match self.posts.iter().position(|p| p.no == after) {
+            Some(i) => &self.posts[i + 1..],
+            None => &self.posts
+        }
+    }
+
+    /// Iterates over every post in the thread, starting with the OP.
+    pub fn iter(&self) -> ::std::slice::Iter<'_, ::Post> {
+        self.posts.iter()
+    }
+
+    /// The number of replies in the thread, that is every post but the OP.
+    pub fn reply_count(&self) -> usize {
+        self.posts.len() - 1
+    }
+
+    /// Returns `true` if the regex matches the name, subject, comment or
+    /// filename of the OP. See `Board::compile_query`.
     pub fn is_match(&self, regex: &::regex::Regex) -> bool {
-        self.topic.is_match(regex)
+        self.op().is_match(regex)
+    }
+
+    /// Returns `true` if the regex matches any of the given fields of the OP.
+    pub fn is_match_in(&self, regex: &::regex::Regex, fields: ::SearchFields)
+        -> bool {
+        self.op().is_match_in(regex, fields)
     }
 
     pub fn is_expired(&self) -> bool {
@@ -145,72 +457,780 @@ impl Thread {
         self.wants_update
     }
 
+    /// Get when the thread was last fetched from the API, if ever.
+    pub fn last_updated(&self) -> Option<DateTime<UTC>> {
+        self.last_updated
+    }
+
     pub fn last_reply(&self) -> Option<::LastReply> {
-        match self.topic.last_replies.last() {
-            Some(r) => Some(r.clone()),
-            None => None
-        }
+        self.op().last_replies.last().cloned()
     }
 
     pub fn url(&self) -> String {
         format!("https://boards.4chan.org/{}/thread/{}",
-                &self.board_name, &self.topic.no)
+                &self.board_name, &self.op().no)
+    }
+
+    /// Get the reply graph of the thread, mapping each post number to the
+    /// numbers of the posts that quote it, in thread order. Every post is a
+    /// key, even if it has no replies. Quotes to posts that are not in the
+    /// thread are ignored.
+    pub fn reply_graph(&self) -> HashMap<u64, Vec<u64>> {
+        // A post can only quote posts made before it, so everything it
+        // quotes is already in the graph after a single pass.
+        let mut graph: HashMap<u64, Vec<u64>> = HashMap::new();
+        for post in self.iter() {
+            for quoted in post.quoted_posts() {
+                if let Some(replies) = graph.get_mut(&quoted) {
+                    replies.push(post.no);
+                }
+            }
+            graph.entry(post.no).or_default();
+        }
+        graph
+    }
+
+    /// Get every post in the thread that has a file attached, starting with
+    /// the OP.
+    pub fn images(&self) -> Vec<&::Post> {
+        self.iter().filter(|p| p.has_file()).collect()
+    }
+
+    /// Get every post in the thread with a file attached that is at least
+    /// `min_w` wide and `min_h` high.
+    pub fn images_larger_than(&self, min_w: u32, min_h: u32) -> Vec<&::Post> {
+        self.iter()
+            .filter(|p| match p.dimensions() {
+                Some((w, h)) => w >= min_w && h >= min_h,
+                None => false
+            })
+            .collect()
+    }
+
+    /// Downloads the full resolution file of every post in the thread into
+    /// the directory, creating it if needed. Files are named `{tim}{ext}`.
+    /// The downloads go through the client of the thread, so they are rate
+    /// limited.
+    ///
+    /// Files that already exist are skipped if they match the MD5 digest of
+    /// the post, and downloaded again otherwise, so an interrupted run can be
+    /// resumed. A failed download doesn't stop the others, it is reported
+    /// instead. Only failing to create the directory is an error.
+    pub fn download_all_media<P: AsRef<Path>>(&self, dir: P)
+        -> ::Result<DownloadReport> {
+        self.download_all_media_as(dir, MediaNaming::Tim)
+    }
+
+    /// Like `download_all_media`, but files are named as `naming` says.
+    pub fn download_all_media_as<P: AsRef<Path>>(&self, dir: P,
+                                                 naming: MediaNaming)
+        -> ::Result<DownloadReport> {
+        let dir = dir.as_ref();
+        try!(fs::create_dir_all(dir));
+
+        let mut report = DownloadReport::default();
+        let mut names = HashSet::new();
+        for post in self.images() {
+            let name = match naming {
+                MediaNaming::Tim => format!("{}{}", post.tim, post.ext),
+                MediaNaming::OriginalName => {
+                    // Images never lack a file name, but two posts can share
+                    // one, in which case the later is prefixed by its `tim`.
+                    let name = post.safe_filename().unwrap_or_default();
+                    if names.contains(&name) {
+                        let prefix = format!("{}_", post.tim);
+                        post.safe_filename_prefixed(&prefix)
+                            .unwrap_or_default()
+                    } else {
+                        name
+                    }
+                }
+            };
+            names.insert(name.clone());
+            let path = dir.join(name);
+            let corrupted = path.exists();
+            if corrupted && is_complete(post, &path) {
+                report.skipped.push(path);
+                continue
+            }
+            match self.download_media(post, &path) {
+                Ok(()) if corrupted => report.corrupted_redownloaded.push(path),
+                Ok(()) => report.downloaded.push(path),
+                Err(e) => report.failed.push((path, e))
+            }
+        }
+        Ok(report)
+    }
+
+    /// Downloads the file of the post to the path. A partial file is
+    /// removed so that it isn't skipped as downloaded next time.
+    fn download_media(&self, post: &::Post, path: &Path) -> ::Result<()> {
+        let mut file = try!(File::create(path));
+        if let Err(e) = post.download_image(&self.board_name, &self.client,
+                                            &mut file) {
+            drop(file);
+            let _ = fs::remove_file(path);
+            return Err(e)
+        }
+        Ok(())
+    }
+
+    /// Get an estimate of the memory the thread takes, in bytes: the size of
+    /// its structs plus the length of their strings and collections. Spare
+    /// capacity, allocator overhead and the shared client aren't counted, so
+    /// the real footprint is somewhat larger.
+    pub fn approx_size_bytes(&self) -> usize {
+        mem::size_of::<Thread>() +
+            self.board_name.len() +
+            self.etag.as_ref().map_or(0, |e| e.len()) +
+            self.posts.len() * mem::size_of::<::Post>() +
+            self.posts.iter().map(post_heap_size).sum::<usize>()
     }
 
     /// Get a `Vec` of all the image urls in the thread.
     pub fn image_urls(&self) -> Vec<String> {
-        let mut images: Vec<String> = Vec::new();
-        let topic_img = self.topic.image_url(&self.board_name);
-        if topic_img.is_some() {
-            images.push(topic_img.unwrap());
-        }
-        for reply in &self.replies {
-            match reply.image_url(&self.board_name) {
-                Some(i) => images.push(i),
-                None => (),
-            }
+        self.iter()
+            .filter_map(|p| p.image_url(&self.board_name))
+            .collect()
+    }
+}
+
+/// A `ThreadDiff` is what changed in a `Thread` during an update.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadDiff {
+    // Posts that were not in the thread before the update.
+    pub new_posts: Vec<::Post>,
+    // Numbers of the posts that are gone since the last update.
+    pub deleted: Vec<u64>,
+    // Whether the OP changed.
+    pub op_changed: bool
+}
+
+impl ThreadDiff {
+    /// Returns `true` if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.new_posts.is_empty() && self.deleted.is_empty() && !self.op_changed
+    }
+}
+
+/// What an update of a `Thread` needs, see `Thread::update_request`.
+pub(crate) struct UpdateRequest {
+    url: String,
+    since: Option<::IfModifiedSince>,
+    etag: Option<String>,
+    // How long to wait before the request, to keep the 10 second throttle.
+    wait: Option<StdDuration>,
+    sender: ::client::Sender
+}
+
+#[allow(deprecated)]
+impl UpdateRequest {
+    /// Makes the request, waiting out the throttle first.
+    pub(crate) fn send(self) -> ::Result<UpdateResponse> {
+        if let Some(wait) = self.wait {
+            sleep(wait);
+        }
+        let mut res = try!(self.sender.get_conditional(&self.url, self.since,
+                                                       self.etag.as_deref()));
+        let buf = try!(::read_text(&mut res));
+        UpdateResponse::new(&self.url, res.status(), res.headers(), &buf)
+    }
+
+    /// Makes the request like `send`, but waits out the throttle with a
+    /// timer rather than sleeping, see `Thread::update_async`.
+    #[cfg(feature = "async")]
+    pub(crate) fn send_async(self) -> ::AsyncFuture<UpdateResponse> {
+        let at = ::std::time::Instant::now() +
+            self.wait.unwrap_or_else(|| StdDuration::from_secs(0));
+        Box::new(::tokio_timer::Delay::new(at)
+            .map_err(::Error::from)
+            .and_then(move |_| {
+                let UpdateRequest { url, since, etag, sender, .. } = self;
+                sender.get_conditional_async(&url, since, etag.as_deref())
+                    .and_then(move |res| UpdateResponse::new(
+                        &url, res.status, &res.headers, &res.text))
+            }))
+    }
+}
+
+/// The response to an `UpdateRequest`, applied with `Thread::apply`.
+pub(crate) enum UpdateResponse {
+    Modified {
+        posts: Vec<::Post>,
+        last_modified: DateTime<UTC>,
+        etag: Option<String>
+    },
+    NotModified,
+    NotFound
+}
+
+#[allow(deprecated)]
+impl UpdateResponse {
+    /// Parses the response to an update of the thread at the url.
+    fn new(url: &str,
+           status: StatusCode,
+           headers: &::reqwest::header::HeaderMap,
+           buf: &str) -> ::Result<UpdateResponse> {
+        match status {
+            StatusCode::OK => {
+                debug!("Got {} bytes for {}", buf.len(), url);
+                let thread: ThreadDeserializer = try!(
+                    ::serde_json::from_str(buf));
+                // A thread always has its OP, so one without posts is gone.
+                if thread.posts.is_empty() {
+                    warn!("No posts in {}, treating it as expired", url);
+                    return Ok(UpdateResponse::NotFound)
+                }
+                Ok(UpdateResponse::Modified {
+                    posts: thread.posts,
+                    last_modified: ::last_modified(headers),
+                    etag: ::etag(headers)
+                })
+            },
+            StatusCode::NOT_MODIFIED => Ok(UpdateResponse::NotModified),
+            StatusCode::NOT_FOUND => Ok(UpdateResponse::NotFound),
+            status => Err(::Error::UnexpectedStatus(status))
         }
-        images
     }
 }
 
+/// A `DownloadReport` is the outcome of `Thread::download_all_media`.
+#[derive(Debug, Default)]
+pub struct DownloadReport {
+    // Paths of the files that were downloaded.
+    pub downloaded: Vec<PathBuf>,
+    // Paths of the files that already existed and were complete.
+    pub skipped: Vec<PathBuf>,
+    // Paths of the files that already existed but didn't match their digest,
+    // and were downloaded again.
+    pub corrupted_redownloaded: Vec<PathBuf>,
+    // Paths of the files that failed to download, with the reason.
+    pub failed: Vec<(PathBuf, ::Error)>
+}
+
+/// How `Thread::download_all_media_as` names the downloaded files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MediaNaming {
+    // `{tim}{ext}`, the name of the file on the server. Always unique.
+    Tim,
+    // The original name of the file as given by `Post::safe_filename`.
+    OriginalName
+}
+
+/// Returned by the callback of a watch to tell whether to keep watching.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchControl {
+    Continue,
+    Stop
+}
+
+/// A `WatchHandle` stops a watch from another thread. Its clones share the
+/// same flag, so keep one and give the watch another.
+#[derive(Clone, Debug, Default)]
+pub struct WatchHandle {
+    stopped: Arc<AtomicBool>
+}
+
+impl WatchHandle {
+    pub fn new() -> WatchHandle {
+        WatchHandle::default()
+    }
+
+    /// Stops every watch using the handle. A watch returns before its next
+    /// request, or shortly if it is sleeping between two of them. A watch
+    /// started with a stopped handle returns at once.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+/// Get the bytes a post owns on the heap, through its strings and
+/// collections.
+fn post_heap_size(post: &::Post) -> usize {
+    let strings = [&post.now, &post.name, &post.trip, &post.email, &post.id,
+                   &post.capcode, &post.sub, &post.com, &post.filename,
+                   &post.ext, &post.md5, &post.tag, &post.semantic_url];
+    let options = [&post.country, &post.country_name, &post.board_flag,
+                   &post.flag_name];
+
+    strings.iter().map(|s| s.len()).sum::<usize>() +
+        options.iter()
+            .map(|s| s.as_ref().map_or(0, |s| s.len()))
+            .sum::<usize>() +
+        post.last_replies.iter()
+            .map(|r| mem::size_of::<::LastReply>() + r.now.len() +
+                 r.name.len() + r.com.len())
+            .sum::<usize>() +
+        post.extra.iter()
+            .map(|(key, value)| key.len() + mem::size_of::<Value>() +
+                 value_heap_size(value))
+            .sum::<usize>()
+}
+
+/// Get the bytes a JSON value owns on the heap, without serializing it.
+fn value_heap_size(value: &Value) -> usize {
+    match *value {
+        Value::String(ref s) => s.len(),
+        Value::Array(ref values) => values.iter()
+            .map(|v| mem::size_of::<Value>() + value_heap_size(v))
+            .sum(),
+        Value::Object(ref map) => map.iter()
+            .map(|(k, v)| {
+                k.len() + mem::size_of::<Value>() + value_heap_size(v)
+            })
+            .sum(),
+        Value::Null | Value::Bool(_) | Value::Number(_) => 0
+    }
+}
+
+/// Returns `true` if the file at the path matches the MD5 digest of the
+/// post. A post without a digest can't be checked, so any file counts.
+fn is_complete(post: &::Post, path: &Path) -> bool {
+    let mut data = Vec::new();
+    if File::open(path).and_then(|mut f| f.read_to_end(&mut data)).is_err() {
+        return false
+    }
+    match post.verify_md5(&data) {
+        Ok(matches) => matches,
+        Err(::Error::NoAttachment) => true,
+        Err(_) => false
+    }
+}
+
+/// Get when a topic was last modified, if the API provided it.
+fn last_modified(topic: &::Post) -> Option<DateTime<UTC>> {
+    if topic.last_modified == 0 {
+        return None
+    }
+    Some(DateTime::<UTC>::from_utc(
+        NaiveDateTime::from_timestamp(topic.last_modified, 0), UTC))
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ThreadDeserializer {
     pub posts: Vec<::Post>
 }
 
 /// A `ThreadCache` is an abstraction around a `HashMap<u64, Thread>`.
+///
+/// A cache created with `with_capacity` evicts the least recently accessed
+/// thread when it is full. A cache created with `new` is unbounded.
 #[derive(Debug)]
 pub struct ThreadCache {
-    pub threads: HashMap<u64, Thread>
+    pub threads: HashMap<u64, Thread>,
+    capacity: Option<usize>,
+    ttl: Option<StdDuration>,
+    // Incremented on every access and recorded in `last_access`. Entries
+    // are only added and removed through `&mut self`, but their ticks are
+    // atomics, so that readers of a cache behind a `RwLock` mark the threads
+    // they access without locking each other out.
+    tick: AtomicUsize,
+    last_access: HashMap<u64, AtomicUsize>
 }
 
+impl Default for ThreadCache {
+    fn default() -> ThreadCache {
+        ThreadCache::new()
+    }
+}
+
+#[allow(deprecated)]
 impl ThreadCache {
     pub fn new() -> ThreadCache {
-        ThreadCache { threads: HashMap::new() }
+        ThreadCache {
+            threads: HashMap::new(),
+            capacity: None,
+            ttl: None,
+            tick: AtomicUsize::new(0),
+            last_access: HashMap::new()
+        }
+    }
+
+    /// Creates a cache holding at most `capacity` threads.
+    pub fn with_capacity(capacity: usize) -> ThreadCache {
+        ThreadCache { capacity: Some(capacity), ..ThreadCache::new() }
     }
 
     pub fn get(&self, thread_no: u64) -> Option<&Thread> {
+        self.touch(thread_no);
         self.threads.get(&thread_no)
     }
 
+    pub fn get_mut(&mut self, thread_no: u64) -> Option<&mut Thread> {
+        self.touch(thread_no);
+        self.threads.get_mut(&thread_no)
+    }
+
+    /// Inserts a thread unless it is already cached. Evicts the least
+    /// recently accessed thread if the cache is full.
     pub fn insert(&mut self, thread: Thread) {
-        self.threads.entry(thread.topic.no).or_insert(thread);
+        let thread_no = thread.op().no;
+        if !self.threads.contains_key(&thread_no) {
+            if let Some(capacity) = self.capacity {
+                while self.threads.len() >= capacity && self.evict() {}
+            }
+        }
+        if self.capacity.is_some() {
+            self.last_access.entry(thread_no)
+                .or_insert_with(|| AtomicUsize::new(0));
+        }
+        self.threads.entry(thread_no).or_insert(thread);
+        self.touch(thread_no);
+    }
+
+    /// Like `insert`, but a thread already in the cache is overwritten,
+    /// unless the cached copy was modified later.
+    pub fn replace(&mut self, thread: Thread) {
+        let thread_no = thread.op().no;
+        match self.threads.get_mut(&thread_no) {
+            Some(cached) => if cached.last_modified <= thread.last_modified {
+                *cached = thread;
+            },
+            None => return self.insert(thread)
+        }
+        self.touch(thread_no);
+    }
+
+    /// Applies the response to an update of a cached thread, which was made
+    /// outside the cache lock, and removes the thread if it expired. Returns
+    /// what changed, or `None` if the thread is no longer cached.
+    pub(crate) fn apply(&mut self, thread_no: u64, response: UpdateResponse)
+        -> Option<ThreadDiff> {
+        let (diff, expired) = {
+            let thread = self.get_mut(thread_no)?;
+            (thread.apply(response), thread.expired)
+        };
+        if expired {
+            self.remove(thread_no);
+        }
+        Some(diff)
     }
 
     pub fn contains(&self, thread_no: u64) -> bool {
+        self.touch(thread_no);
         self.threads.contains_key(&thread_no)
     }
 
-    pub fn remove(&mut self, thread_no: u64) {
-        self.threads.remove(&thread_no);
+    pub fn remove(&mut self, thread_no: u64) -> Option<Thread> {
+        self.last_access.remove(&thread_no);
+        self.threads.remove(&thread_no)
+    }
+
+    /// Removes the threads that are flagged as expired, without making any
+    /// request. Returns the number of threads removed.
+    pub fn purge_expired(&mut self) -> usize {
+        let expired = self.threads.values()
+            .filter(|t| t.expired)
+            .map(|t| t.op().no)
+            .collect::<Vec<u64>>();
+        for &thread_no in &expired {
+            self.remove(thread_no);
+        }
+        expired.len()
+    }
+
+    /// Get an estimate of the memory the cached threads take, in bytes. Each
+    /// entry counts its key and `Thread::approx_size_bytes`, which includes
+    /// the `Thread` stored in the map. Like there, spare capacity of the maps
+    /// isn't counted.
+    pub fn approx_size_bytes(&self) -> usize {
+        let lru_entry = mem::size_of::<u64>() + mem::size_of::<AtomicUsize>();
+        mem::size_of::<ThreadCache>() +
+            self.last_access.len() * lru_entry +
+            self.threads.values()
+                .map(|t| mem::size_of::<u64>() + t.approx_size_bytes())
+                .sum::<usize>()
+    }
+
+    /// The number of cached threads.
+    pub fn len(&self) -> usize {
+        self.threads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    /// The maximum number of cached threads, or `None` if unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Sets how long a cached thread is trusted after it was last updated.
+    /// Without a TTL, cached threads are updated every time they are
+    /// requested.
+    pub fn set_ttl(&mut self, ttl: StdDuration) {
+        self.ttl = Some(ttl);
+    }
+
+    pub fn ttl(&self) -> Option<StdDuration> {
+        self.ttl
+    }
+
+    /// Returns `true` if the thread should be updated before it is served
+    /// from the cache, that is if it was never updated or it is older than
+    /// the TTL. Always `true` if there is no TTL.
+    pub fn needs_update(&self, thread: &Thread) -> bool {
+        match (self.ttl, thread.last_updated) {
+            (Some(ttl), Some(last_updated)) => {
+                // An error means the last update is in the future.
+                UTC::now().signed_duration_since(last_updated).to_std()
+                    .map(|age| age > ttl)
+                    .unwrap_or(false)
+            },
+            _ => true
+        }
+    }
+
+    /// Marks a thread as the most recently accessed.
+    fn touch(&self, thread_no: u64) {
+        if let Some(last_access) = self.last_access.get(&thread_no) {
+            let tick = self.tick.fetch_add(1, Ordering::Relaxed) + 1;
+            last_access.store(tick, Ordering::Relaxed);
+        }
+    }
+
+    /// Removes the least recently accessed thread. Returns `false` if there
+    /// was nothing to remove.
+    fn evict(&mut self) -> bool {
+        let oldest = self.threads.keys()
+            .min_by_key(|no| {
+                self.last_access.get(no)
+                    .map_or(0, |tick| tick.load(Ordering::Relaxed))
+            })
+            .cloned();
+        match oldest {
+            Some(thread_no) => {
+                self.remove(thread_no);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Saves the cached threads as JSON into the writer, along with the
+    /// capacity and TTL of the cache.
+    pub fn save<W: Write>(&self, w: &mut W) -> ::Result<()> {
+        let saved = SavedCache {
+            capacity: self.capacity,
+            ttl: self.ttl,
+            threads: self.threads.values()
+                .map(SavedThread::from_thread)
+                .collect()
+        };
+        try!(::serde_json::to_writer(w, &saved));
+        Ok(())
+    }
+
+    /// Loads a cache saved with `save`, with the capacity and TTL it had. The
+    /// threads use the client to update, as clients are not saved.
+    pub fn load<R: Read>(r: &mut R, client: Arc<Mutex<::Client>>)
+        -> ::Result<ThreadCache> {
+        let saved: SavedCache = try!(::serde_json::from_reader(r));
+
+        let mut cache = match saved.capacity {
+            Some(capacity) => ThreadCache::with_capacity(capacity),
+            None => ThreadCache::new()
+        };
+        if let Some(ttl) = saved.ttl {
+            cache.set_ttl(ttl);
+        }
+        for thread in saved.threads {
+            cache.insert(thread.into_thread(client.clone()));
+        }
+        Ok(cache)
+    }
+}
+
+/// The schema of `ThreadCache::save`.
+#[derive(Deserialize, Serialize)]
+struct SavedCache {
+    capacity: Option<usize>,
+    ttl: Option<StdDuration>,
+    threads: Vec<SavedThread>
+}
+
+/// The schema of `Thread::to_json`.
+#[derive(Deserialize, Serialize)]
+struct ThreadJson {
+    board: String,
+    no: u64,
+    expired: bool,
+    op: PostJson,
+    // The replies.
+    posts: Vec<PostJson>
+}
+
+/// A post in the schema of `Thread::to_json`. Independent from the fields
+/// of the API, so that it stays the same if they change.
+#[derive(Deserialize, Serialize)]
+struct PostJson {
+    no: u64,
+    // The OP of the thread, 0 for the OP itself.
+    reply_to: u64,
+    time: u32,
+    name: String,
+    tripcode: Option<String>,
+    poster_id: Option<String>,
+    capcode: Option<String>,
+    email: Option<String>,
+    country: Option<String>,
+    subject: Option<String>,
+    // The HTML of the comment.
+    comment: Option<String>,
+    sticky: bool,
+    closed: bool,
+    file: Option<FileJson>
+}
+
+/// The file of a post in the schema of `Thread::to_json`.
+#[derive(Deserialize, Serialize)]
+struct FileJson {
+    name: String,
+    ext: String,
+    tim: u64,
+    size: u32,
+    md5: String,
+    width: u16,
+    height: u16,
+    thumbnail_width: u8,
+    thumbnail_height: u8,
+    spoiler: bool,
+    deleted: bool
+}
+
+impl PostJson {
+    fn from_post(post: &::Post) -> PostJson {
+        let text = |s: &str| if s.is_empty() { None } else { Some(s.into()) };
+        let file = if post.filename.is_empty() {
+            None
+        } else {
+            Some(FileJson {
+                name: post.filename.clone(),
+                ext: post.ext.clone(),
+                tim: post.tim,
+                size: post.fsize,
+                md5: post.md5.clone(),
+                width: post.w,
+                height: post.h,
+                thumbnail_width: post.tn_w,
+                thumbnail_height: post.tn_h,
+                spoiler: post.is_spoiler(),
+                deleted: post.file_deleted()
+            })
+        };
+        PostJson {
+            no: post.no,
+            reply_to: post.resto,
+            time: post.time,
+            name: post.name.clone(),
+            tripcode: text(&post.trip),
+            poster_id: text(&post.id),
+            capcode: text(&post.capcode),
+            email: text(&post.email),
+            country: post.country.clone(),
+            subject: text(&post.sub),
+            comment: text(&post.com),
+            sticky: post.sticky == 1,
+            closed: post.closed == 1,
+            file
+        }
+    }
+
+    fn into_post(self) -> ::Post {
+        let mut post = ::Post {
+            no: self.no,
+            resto: self.reply_to,
+            time: self.time,
+            name: self.name,
+            trip: self.tripcode.unwrap_or_default(),
+            id: self.poster_id.unwrap_or_default(),
+            capcode: self.capcode.unwrap_or_default(),
+            email: self.email.unwrap_or_default(),
+            country: self.country,
+            sub: self.subject.unwrap_or_default(),
+            com: self.comment.unwrap_or_default(),
+            sticky: self.sticky as u8,
+            closed: self.closed as u8,
+            ..::Post::default()
+        };
+        if let Some(file) = self.file {
+            post.filename = file.name;
+            post.ext = file.ext;
+            post.tim = file.tim;
+            post.fsize = file.size;
+            post.md5 = file.md5;
+            post.w = file.width;
+            post.h = file.height;
+            post.tn_w = file.thumbnail_width;
+            post.tn_h = file.thumbnail_height;
+            post.spoiler = file.spoiler as u8;
+            post.file_deleted = file.deleted as u8;
+        }
+        post
+    }
+}
+
+/// The part of a `Thread` that is saved by `ThreadCache::save`.
+#[derive(Deserialize, Serialize)]
+struct SavedThread {
+    board_name: String,
+    posts: Vec<::Post>,
+    expired: bool,
+    last_reply_no: u64,
+    // Timestamp of when the thread was last modified.
+    last_modified: Option<i64>,
+    etag: Option<String>,
+    // Whether the OP is a catalog preview, which `Post` doesn't serialize.
+    #[serde(default)]
+    preview: bool
+}
+
+impl SavedThread {
+    fn from_thread(thread: &Thread) -> SavedThread {
+        SavedThread {
+            board_name: thread.board_name.clone(),
+            posts: thread.posts.clone(),
+            expired: thread.expired,
+            last_reply_no: thread.last_reply_no,
+            last_modified: thread.last_modified.map(|dt| dt.timestamp()),
+            etag: thread.etag.clone(),
+            preview: thread.op().preview
+        }
+    }
+
+    fn into_thread(mut self, client: Arc<Mutex<::Client>>) -> Thread {
+        self.posts[0].preview = self.preview;
+        Thread {
+            board_name: self.board_name,
+            client,
+            posts: self.posts,
+            expired: self.expired,
+            wants_update: !self.expired,
+            last_reply_no: self.last_reply_no,
+            last_updated: None,
+            last_modified: self.last_modified.map(|ts| {
+                DateTime::<UTC>::from_utc(
+                    NaiveDateTime::from_timestamp(ts, 0), UTC)
+            }),
+            etag: self.etag
+        }
     }
 }
 
+/// Prints the OP followed by every reply, separated by blank lines.
+#[allow(deprecated)]
 impl fmt::Display for Thread {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "no: {}, last_modified: {}, expired: {}",
-               self.topic.no, self.topic.last_modified, self.expired)
+        try!(write!(f, "{}", self.op()));
+        for reply in self.replies() {
+            try!(write!(f, "\n\n{}", reply));
+        }
+        Ok(())
     }
 }
 
@@ -222,3 +1242,315 @@ impl fmt::Display for ThreadCache {
                .join(", "))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    fn post(no: u64, resto: u64, com: &str) -> ::Post {
+        let mut post: ::Post = ::serde_json::from_str(&format!(
+            r#"{{"no": {}, "resto": {}, "now": "", "time": 0}}"#,
+            no, resto)).unwrap();
+        post.com = com.to_string();
+        post
+    }
+
+    fn quote(no: u64) -> String {
+        format!(r##"<a href="#p{0}" class="quotelink">&gt;&gt;{0}</a><br>"##,
+                no)
+    }
+
+    #[test]
+    fn thread_reply_graph_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+        thread.posts.push(post(2, 1, &quote(1)));
+        thread.posts.push(post(3, 1, &(quote(1) + &quote(2))));
+        // Quotes a deleted post.
+        thread.posts.push(post(4, 1, &quote(99)));
+
+        let graph = thread.reply_graph();
+        assert_eq!(4, graph.len());
+        assert_eq!(vec![2, 3], graph[&1]);
+        assert_eq!(vec![3], graph[&2]);
+        assert!(graph[&3].is_empty());
+        assert!(graph[&4].is_empty());
+        assert!(!graph.contains_key(&99));
+    }
+
+    #[test]
+    fn thread_from_topic_is_preview_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+        assert!(thread.op().is_preview());
+        assert!(!post(1, 0, "").is_preview());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn thread_topic_is_op_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let deserializer = ::ThreadDeserializer {
+            posts: vec![post(1, 0, "op"), post(2, 1, "reply")]
+        };
+        let thread = ::Thread::from_deserializer(deserializer, "g", client);
+        assert_eq!(thread.op(), thread.topic());
+        assert_eq!(&thread.posts()[1..], thread.replies());
+    }
+
+    #[test]
+    fn thread_posts_since_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+        thread.posts.push(post(2, 1, ""));
+        thread.posts.push(post(5, 1, ""));
+
+        let nos = |posts: &[::Post]| posts.iter().map(|p| p.no)
+            .collect::<Vec<u64>>();
+        assert_eq!(vec![2, 5], nos(thread.posts_since(1)));
+        assert_eq!(vec![5], nos(thread.posts_since(2)));
+        assert!(thread.posts_since(5).is_empty());
+        assert_eq!(vec![1, 2, 5], nos(thread.posts_since(3)));
+    }
+
+    #[test]
+    fn thread_merge_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut live = ::Thread::from_topic(post(1, 0, "live"), "g",
+                                            client.clone());
+        live.posts.push(post(3, 1, "live"));
+        live.posts.push(post(4, 1, "live"));
+        live.last_modified = Some(::chrono::UTC::now());
+
+        let mut saved = ::Thread::from_topic(post(1, 0, "saved"), "g", client);
+        saved.posts[0].last_modified = 100;
+        saved.posts.push(post(2, 1, "saved"));
+        saved.posts.push(post(3, 1, "saved"));
+        saved.expired = true;
+
+        live.merge(saved);
+        let coms = live.iter()
+            .map(|p| (p.no, p.com.as_str()))
+            .collect::<Vec<(u64, &str)>>();
+        // The saved OP was modified later, but the live copy wins the rest.
+        assert_eq!(vec![(1, "saved"), (2, "saved"), (3, "live"), (4, "live")],
+                   coms);
+        assert!(live.is_expired());
+    }
+
+    #[test]
+    fn thread_json_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut thread = ::Thread::from_topic(post(1, 0, "op"), "g",
+                                              client.clone());
+        thread.posts.push(post(2, 1, &quote(1)));
+
+        let json = thread.to_json().unwrap();
+        let value: ::serde_json::Value = ::serde_json::from_str(&json)
+            .unwrap();
+        assert_eq!("g", value["board"]);
+        assert_eq!(1, value["no"]);
+        assert_eq!(2, value["posts"][0]["no"]);
+        assert_eq!(1, value["posts"][0]["reply_to"]);
+        assert_eq!(::serde_json::Value::Null, value["op"]["file"]);
+        // Neither internal nor unknown fields leak into the export.
+        assert!(value["op"].get("preview").is_none());
+        assert!(value["op"].get("com").is_none());
+
+        let imported = ::Thread::from_json(&json, client).unwrap();
+        let posts = |t: &::Thread| t.posts().iter()
+            .map(|p| (p.no, p.resto, p.com.clone()))
+            .collect::<Vec<(u64, u64, String)>>();
+        assert_eq!(posts(&thread), posts(&imported));
+        assert_eq!("g", imported.board_name);
+    }
+
+    #[test]
+    fn thread_images_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+        for &(no, w, h) in &[(2, 1920, 1080), (3, 200, 100)] {
+            let mut reply = post(no, 1, "");
+            reply.filename = "image".to_string();
+            reply.ext = ".jpg".to_string();
+            reply.w = w;
+            reply.h = h;
+            thread.posts.push(reply);
+        }
+
+        let images: Vec<u64> = thread.images().iter().map(|p| p.no).collect();
+        assert_eq!(vec![2, 3], images);
+        let large: Vec<u64> = thread.images_larger_than(800, 600).iter()
+            .map(|p| p.no)
+            .collect();
+        assert_eq!(vec![2], large);
+    }
+
+    #[test]
+    fn thread_download_all_media_skips_existing_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+        let mut reply = post(2, 1, "");
+        reply.filename = "image".to_string();
+        reply.ext = ".jpg".to_string();
+        reply.tim = 1492218205123;
+        reply.md5 = "nhB9nTcrtoJr2B01QqQZ1g==".to_string();
+        thread.posts.push(reply);
+
+        let dir = ::std::env::temp_dir().join("clover-download-all-media");
+        ::std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("1492218205123.jpg");
+        ::std::fs::File::create(&path).unwrap()
+            .write_all(b"The quick brown fox jumps over the lazy dog")
+            .unwrap();
+
+        let report = thread.download_all_media(&dir).unwrap();
+        assert_eq!(vec![path], report.skipped);
+        assert!(report.downloaded.is_empty());
+        assert!(report.corrupted_redownloaded.is_empty());
+        assert!(report.failed.is_empty());
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn thread_download_all_media_original_name_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+        let mut reply = post(2, 1, "");
+        reply.filename = "my/image".to_string();
+        reply.ext = ".jpg".to_string();
+        reply.tim = 1492218205123;
+        reply.md5 = "nhB9nTcrtoJr2B01QqQZ1g==".to_string();
+        thread.posts.push(reply);
+
+        let dir = ::std::env::temp_dir().join("clover-download-original-name");
+        ::std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("my_image.jpg");
+        ::std::fs::File::create(&path).unwrap()
+            .write_all(b"The quick brown fox jumps over the lazy dog")
+            .unwrap();
+
+        let report = thread.download_all_media_as(
+            &dir, ::MediaNaming::OriginalName).unwrap();
+        assert_eq!(vec![path], report.skipped);
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn thread_watch_with_stopped_handle_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        // No request is made, or it would fail.
+        client.lock().unwrap().set_api_base("http://127.0.0.1:1");
+        let mut thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+
+        let handle = ::WatchHandle::new();
+        handle.clone().stop();
+        thread.watch_with_handle(::std::time::Duration::from_secs(10), &handle,
+                                 |_| panic!("Stopped watch was notified"))
+            .unwrap();
+    }
+
+    #[test]
+    fn thread_fetch_full_test() {
+        // Answers with the full thread, whatever the request.
+        let (base, server) = ::mock::serve(vec![::mock::response("200 OK",
+            r#"{"posts": [
+                {"no": 1, "resto": 0, "now": "", "time": 0},
+                {"no": 2, "resto": 1, "now": "", "time": 0}
+            ]}"#)]);
+
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        client.lock().unwrap().set_api_base(&base);
+        let mut thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+        assert!(!thread.is_loaded());
+
+        thread.fetch_full().unwrap();
+        server.join().unwrap();
+        assert!(thread.is_loaded());
+        assert_eq!(vec![1, 2],
+                   thread.posts().iter().map(|p| p.no).collect::<Vec<u64>>());
+
+        // No request is made once the thread is loaded.
+        thread.fetch_full().unwrap();
+    }
+
+    #[test]
+    fn thread_update_without_posts_test() {
+        let (base, server) = ::mock::serve(vec![
+            ::mock::response("200 OK", r#"{"posts": []}"#)]);
+
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        client.lock().unwrap().set_api_base(&base);
+        let mut thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+
+        assert!(thread.update_diff().unwrap().is_empty());
+        server.join().unwrap();
+        assert!(thread.expired);
+        assert_eq!(1, thread.op().no);
+    }
+
+    #[test]
+    fn thread_approx_size_bytes_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut thread = ::Thread::from_topic(post(1, 0, ""), "g", client);
+        let empty = thread.approx_size_bytes();
+        assert!(empty >= ::std::mem::size_of::<::Thread>() +
+                         ::std::mem::size_of::<::Post>());
+
+        thread.posts.push(post(2, 1, &"a".repeat(1000)));
+        assert!(thread.approx_size_bytes() >=
+                empty + ::std::mem::size_of::<::Post>() + 1000);
+
+        // A copy measures the same, whatever the spare capacity.
+        assert_eq!(thread.approx_size_bytes(),
+                   thread.clone().approx_size_bytes());
+
+        let mut cache = ::ThreadCache::new();
+        cache.insert(thread.clone());
+        assert!(cache.approx_size_bytes() > thread.approx_size_bytes());
+    }
+
+    #[test]
+    fn thread_cache_save_load_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut thread = ::Thread::from_topic(post(1, 0, "op"), "g",
+                                              client.clone());
+        thread.posts.push(post(2, 1, &quote(1)));
+
+        let mut cache = ::ThreadCache::with_capacity(10);
+        cache.set_ttl(::std::time::Duration::from_secs(60));
+        cache.insert(thread);
+
+        let mut buf = Vec::new();
+        cache.save(&mut buf).unwrap();
+        let loaded = ::ThreadCache::load(&mut &buf[..], client).unwrap();
+        assert_eq!(Some(10), loaded.capacity());
+        assert_eq!(Some(::std::time::Duration::from_secs(60)), loaded.ttl());
+
+        let thread = loaded.get(1).unwrap();
+        assert_eq!("g", thread.board_name);
+        assert_eq!("op", thread.op().com);
+        assert!(thread.op().is_preview());
+        assert_eq!(vec![1], thread.replies()[0].quoted_posts());
+    }
+
+    #[test]
+    fn thread_cache_lru_test() {
+        let client = Arc::new(Mutex::new(::Client::new().unwrap()));
+        let mut cache = ::ThreadCache::with_capacity(2);
+        for no in 1..3 {
+            cache.insert(::Thread::from_topic(post(no, 0, ""), "g",
+                                              client.clone()));
+        }
+        // Thread 1 is now more recent than thread 2.
+        assert!(cache.get(1).is_some());
+
+        cache.insert(::Thread::from_topic(post(3, 0, ""), "g", client));
+        assert_eq!(2, cache.len());
+        assert_eq!(Some(2), cache.capacity());
+        assert!(cache.contains(1));
+        assert!(!cache.contains(2));
+        assert!(cache.contains(3));
+    }
+}